@@ -1,42 +1,264 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
-use glam::{Mat4, Vec3A};
+use glam::Mat4;
 use winit::application::ApplicationHandler;
 use winit::dpi::PhysicalSize;
-use winit::event::WindowEvent;
-use winit::event_loop::ActiveEventLoop;
-use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::event::{DeviceEvent, DeviceId, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
 use winit::window::{Window, WindowId};
 
-use crate::contexts::Draw3dContext;
+use crate::config::AppConfig;
+use crate::contexts::Record3dContext;
+use crate::display::{self, DisplaySettings, WindowMode};
 use crate::game::Game;
-use crate::resolution::Resolution;
+use crate::headless;
+use crate::input::{actions, InputState};
+use crate::recording::{InputPlayback, InputRecorder};
+use crate::renderer::RenderTarget;
+use crate::textures;
+use crate::time::{FixedTimestep, GameTime};
 use crate::virtual_gpu::VirtualGpu;
-use crate::wgpu_setup;
+use crate::wgpu_setup::{self, AdapterNotFound};
 
-pub struct StateApplication {
+/// Entry point for running games. Extra scenes can be registered and
+/// switched between at runtime with Tab.
+pub struct App {
+    config: AppConfig,
+    scenes: Vec<Box<dyn Game>>,
+}
+
+impl App {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            config,
+            scenes: Vec::new(),
+        }
+    }
+
+    pub fn register_scene(mut self, scene: Box<dyn Game>) -> Self {
+        self.scenes.push(scene);
+        self
+    }
+
+    /// Runs the app, starting with the given game.
+    pub fn run(self, game: Box<dyn Game>) {
+        let mut scenes = vec![game];
+        scenes.extend(self.scenes);
+
+        let runner = GameRunner::new(&self.config, scenes);
+
+        if self.config.headless {
+            headless::run(&self.config, runner);
+            return;
+        }
+
+        let event_loop = EventLoop::new().unwrap();
+        event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
+
+        let mut window_state = StateApplication::new(self.config, runner);
+        let _ = event_loop.run_app(&mut window_state);
+    }
+}
+
+/// Owns the scenes and their input, and steps them one tick at a time.
+/// Shared between the windowed and headless apps, so both behave the same
+/// for a given recording.
+pub struct GameRunner {
+    pub scenes: Vec<Box<dyn Game>>,
+    active_scene: usize,
+    input: InputState,
+    recorder: Option<InputRecorder>,
+    playback: Option<InputPlayback>,
+    time: GameTime,
+    frame_limit: Option<u64>,
+}
+
+impl GameRunner {
+    pub fn new(config: &AppConfig, scenes: Vec<Box<dyn Game>>) -> Self {
+        Self {
+            scenes,
+            active_scene: 0,
+            input: InputState::with_default_bindings(),
+            recorder: config.record.as_deref().map(InputRecorder::create),
+            playback: config.playback.as_deref().map(InputPlayback::load),
+            time: GameTime::new(config.tick_rate),
+            frame_limit: config.frames,
+        }
+    }
+
+    pub fn init(&mut self, gpu: &mut VirtualGpu) {
+        for scene in self.scenes.iter_mut() {
+            scene.init(gpu);
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.finish(self.time.frame, self.input.frame_events());
+        }
+
+        for scene in self.scenes.iter_mut() {
+            scene.shutdown();
+        }
+    }
+
+    /// Live input is ignored while a recording is being played back.
+    pub fn is_playing_back(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn input_mut(&mut self) -> &mut InputState {
+        &mut self.input
+    }
+
+    /// True once the frame limit is reached, or a recorded close request was played back.
+    pub fn should_exit(&self) -> bool {
+        self.frame_limit
+            .is_some_and(|limit| self.time.frame >= limit)
+            || (self.is_playing_back() && self.input.close_requested())
+    }
+
+    /// True once the recording has ended, or there was none to begin with.
+    pub fn playback_finished(&self) -> bool {
+        self.playback.is_none()
+    }
+
+    /// Updates the active scene for a single tick. Input received since the
+    /// last tick is applied to this one.
+    pub fn tick(&mut self, gpu: &mut VirtualGpu, present_modes: &[wgpu::PresentMode]) {
+        if let Some(playback) = self.playback.as_mut() {
+            for event in playback.events(self.time.frame) {
+                self.input.apply(*event);
+            }
+        }
+
+        self.handle_hotkeys(gpu, present_modes);
+
+        // The scene stays as it was while the frame debugger has it frozen
+        if !gpu.frame_debugger.is_frozen() {
+            update_environment(gpu, &self.input);
+            self.scenes[self.active_scene].update(&self.input, &self.time);
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(self.time.frame, self.input.frame_events());
+        }
+
+        self.input.end_frame();
+        self.time.advance();
+
+        if self
+            .playback
+            .as_ref()
+            .is_some_and(|playback| playback.is_finished(self.time.frame))
+        {
+            println!("Playback finished after {} frames.", self.time.frame);
+            self.playback = None;
+        }
+    }
+
+    /// Draws the active scene, `alpha` of the way between the last two ticks.
+    pub fn draw(&mut self, gpu: &mut VirtualGpu, alpha: f32) {
+        if gpu.frame_debugger.is_frozen() {
+            gpu.draw_frame_debugger();
+            return;
+        }
+
+        gpu.push_matrix(Mat4::IDENTITY);
+        gpu.set_texture(textures::DEFAULT_TEXTURE);
+        gpu.set_matcap(textures::DEFAULT_MATCAP);
+
+        let record_start = Instant::now();
+        self.scenes[self.active_scene].draw(gpu, alpha);
+        gpu.record_time = record_start.elapsed();
+
+        if gpu.show_stats || gpu.show_culling {
+            gpu.draw_stats_overlay();
+        }
+    }
+
+    fn next_scene(&mut self) {
+        self.active_scene = (self.active_scene + 1) % self.scenes.len();
+        println!(
+            "Switched to scene: {}",
+            self.scenes[self.active_scene].name()
+        );
+    }
+
+    fn handle_hotkeys(&mut self, gpu: &mut VirtualGpu, present_modes: &[wgpu::PresentMode]) {
+        if self.input.action_pressed(actions::NEXT_SCENE) {
+            self.next_scene();
+        }
+
+        if self.input.action_pressed(actions::TOGGLE_STATS) {
+            gpu.show_stats = !gpu.show_stats;
+        }
+
+        if self.input.action_pressed(actions::TOGGLE_CULLING_STATS) {
+            gpu.show_culling = !gpu.show_culling;
+        }
+
+        if self.input.action_pressed(actions::PRINT_RESOURCES) {
+            println!("{}", gpu.resources());
+        }
+
+        if self.input.action_pressed(actions::TOGGLE_FRAME_DEBUGGER) {
+            gpu.toggle_frame_debugger();
+        }
+
+        let debugger = &mut gpu.frame_debugger;
+        if self.input.action_pressed(actions::NEXT_COMMAND) {
+            debugger.step(true);
+        }
+        if self.input.action_pressed(actions::PREVIOUS_COMMAND) {
+            debugger.step(false);
+        }
+        if self.input.action_pressed(actions::NEXT_DRAW) {
+            debugger.step_draw(true);
+        }
+        if self.input.action_pressed(actions::PREVIOUS_DRAW) {
+            debugger.step_draw(false);
+        }
+
+        let display = &mut gpu.display;
+
+        if self.input.action_pressed(actions::CYCLE_WINDOW_MODE) {
+            display.window_mode = display.window_mode.next();
+        }
+
+        if self.input.action_pressed(actions::CYCLE_PRESENT_MODE) {
+            display.present_mode = display::next_present_mode(display.present_mode, present_modes);
+        }
+
+        if self.input.action_pressed(actions::CYCLE_FRAME_LATENCY) {
+            display.frame_latency = display::next_frame_latency(display.frame_latency);
+        }
+    }
+}
+
+struct StateApplication {
     state: Option<State>,
-    game: Game,
-    last_frame: Instant,
-    frame_time: Duration,
+    runner: GameRunner,
+    timestep: FixedTimestep,
+    config: AppConfig,
 }
 
 impl StateApplication {
-    pub fn new(fps: f32) -> Self {
+    fn new(config: AppConfig, runner: GameRunner) -> Self {
         Self {
             state: None,
-            game: Game::new(),
-            last_frame: Instant::now(),
-            frame_time: Duration::from_secs_f32(1.0 / fps),
+            runner,
+            timestep: FixedTimestep::new(config.tick_rate, config.max_ticks_per_frame),
+            config,
         }
     }
 }
 
 impl ApplicationHandler for StateApplication {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let resolution = Resolution::Full;
-        let (width, height) = resolution.dimensions();
+        let (width, height) = self.config.resolution.dimensions();
 
         let inner_size = PhysicalSize { width, height };
 
@@ -49,12 +271,38 @@ impl ApplicationHandler for StateApplication {
             )
             .unwrap();
 
-        let mut state = State::new(window);
+        if self.config.window_mode != WindowMode::Windowed {
+            window.set_fullscreen(self.config.window_mode.fullscreen(&window));
+        }
+
+        let mut state = match State::new(Arc::new(window), &self.config) {
+            Ok(state) => state,
+            Err(e) => {
+                println!("{e}");
+                event_loop.exit();
+                return;
+            }
+        };
 
-        self.game.init(&mut state.virtual_gpu);
+        self.runner.init(&mut state.virtual_gpu);
 
         self.state = Some(state);
-        self.last_frame = Instant::now();
+        self.timestep.reset();
+    }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        if !self.runner.is_playing_back() {
+            self.runner.input_mut().handle_device_event(&event);
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.runner.shutdown();
     }
 
     fn window_event(
@@ -63,9 +311,15 @@ impl ApplicationHandler for StateApplication {
         window_id: WindowId,
         event: WindowEvent,
     ) {
-        let window = self.state.as_ref().unwrap().window();
+        let Some(state) = self.state.as_ref() else {
+            return;
+        };
+
+        if state.window().id() == window_id {
+            if !self.runner.is_playing_back() {
+                self.runner.input_mut().handle_window_event(&event);
+            }
 
-        if window.id() == window_id {
             match event {
                 WindowEvent::CloseRequested => {
                     event_loop.exit();
@@ -74,64 +328,43 @@ impl ApplicationHandler for StateApplication {
                     self.state.as_mut().unwrap().resize(physical_size);
                 }
                 WindowEvent::RedrawRequested => {
-                    let now = Instant::now();
-
-                    let diff = now.duration_since(self.last_frame);
-                    if diff >= self.frame_time {
-                        self.last_frame = now;
-                        self.state.as_mut().unwrap().update();
-                        self.game.update();
-                        self.game
-                            .draw(&mut self.state.as_mut().unwrap().virtual_gpu);
-                        self.state.as_mut().unwrap().render().unwrap();
-                    }
-                    self.state.as_ref().unwrap().window().request_redraw();
-                }
-                WindowEvent::KeyboardInput { event, .. } => {
-                    let state = &mut self.state.as_mut().unwrap();
-                    // Check the key event state and handle accordingly
-                    match event.state.is_pressed() {
-                        true => match event.physical_key {
-                            PhysicalKey::Code(KeyCode::KeyW) => state.camera_delta.z += 1.0,
-                            PhysicalKey::Code(KeyCode::KeyS) => state.camera_delta.z -= 1.0,
-                            PhysicalKey::Code(KeyCode::KeyQ) => state.camera_delta.x += 1.0,
-                            PhysicalKey::Code(KeyCode::KeyE) => state.camera_delta.x -= 1.0,
-                            PhysicalKey::Code(KeyCode::KeyR) => state.camera_delta.y += 1.0,
-                            PhysicalKey::Code(KeyCode::KeyF) => state.camera_delta.y -= 1.0,
-                            PhysicalKey::Code(KeyCode::KeyA) => state.camera_yaw_delta = 1.0,
-                            PhysicalKey::Code(KeyCode::KeyD) => state.camera_yaw_delta = -1.0,
-                            PhysicalKey::Code(KeyCode::KeyO) => {
-                                state
-                                    .virtual_gpu
-                                    .environment_map
-                                    .uniforms
-                                    .environment_color_strength
-                                    .w += 0.1
-                            }
-                            PhysicalKey::Code(KeyCode::KeyL) => {
-                                state
-                                    .virtual_gpu
-                                    .environment_map
-                                    .uniforms
-                                    .environment_color_strength
-                                    .w -= 0.1
-                            }
-                            _ => {}
-                        },
-                        false => {
-                            match event.physical_key {
-                                PhysicalKey::Code(KeyCode::KeyW)
-                                | PhysicalKey::Code(KeyCode::KeyS) => state.camera_delta.z = 0.0,
-                                PhysicalKey::Code(KeyCode::KeyQ)
-                                | PhysicalKey::Code(KeyCode::KeyE) => state.camera_delta.x = 0.0,
-                                PhysicalKey::Code(KeyCode::KeyR)
-                                | PhysicalKey::Code(KeyCode::KeyF) => state.camera_delta.y = 0.0,
-                                PhysicalKey::Code(KeyCode::KeyA)
-                                | PhysicalKey::Code(KeyCode::KeyD) => state.camera_yaw_delta = 0.0,
-                                _ => {}
+                    if self.state.as_ref().unwrap().is_device_lost() {
+                        println!("Device lost, rebuilding gpu resources...");
+                        match self.state.take().unwrap().rebuild(&self.config) {
+                            Ok(state) => self.state = Some(state),
+                            Err(e) => {
+                                println!("{e}");
+                                event_loop.exit();
+                                return;
                             }
                         }
                     }
+
+                    let state = self.state.as_mut().unwrap();
+                    for _ in 0..self.timestep.advance() {
+                        self.runner
+                            .tick(&mut state.virtual_gpu, &state.present_modes);
+
+                        if self.runner.should_exit() {
+                            event_loop.exit();
+                            return;
+                        }
+                    }
+
+                    self.runner
+                        .draw(&mut state.virtual_gpu, self.timestep.alpha());
+                    state.apply_display_settings();
+
+                    match state.render() {
+                        Ok(()) => {}
+                        Err(wgpu::SurfaceError::OutOfMemory) => {
+                            println!("Surface out of memory, exiting.");
+                            event_loop.exit();
+                            return;
+                        }
+                        Err(e) => println!("Surface error: {e}"),
+                    }
+                    self.state.as_ref().unwrap().window().request_redraw();
                 }
                 _ => {}
             }
@@ -140,86 +373,161 @@ impl ApplicationHandler for StateApplication {
 }
 
 pub struct State {
-    surface: wgpu::Surface<'static>,
+    // Shared with the render thread, which acquires and presents its textures
+    surface: Arc<wgpu::Surface<'static>>,
     config: wgpu::SurfaceConfiguration,
 
     window: Arc<Window>,
+    device_lost: Arc<AtomicBool>,
 
-    camera_delta: Vec3A,
-    camera_yaw_delta: f32,
+    // The currently applied display settings, and what the surface supports
+    display: DisplaySettings,
+    present_modes: Vec<wgpu::PresentMode>,
 
     pub virtual_gpu: VirtualGpu,
 }
 
 impl State {
-    pub fn new(window: Window) -> Self {
-        let window_arc = Arc::new(window);
-        let size = window_arc.inner_size();
-        let instance = wgpu_setup::create_gpu_instance();
-        let surface = instance.create_surface(window_arc.clone()).unwrap();
-        let adapter = wgpu_setup::create_adapter(instance, &surface);
-        let (device, queue) = wgpu_setup::create_device(&adapter);
+    pub fn new(window: Arc<Window>, app_config: &AppConfig) -> Result<Self, AdapterNotFound> {
+        let size = window.inner_size();
+        let backends = app_config.backend.backends();
+        let instance = wgpu_setup::create_gpu_instance(backends);
+        let surface = instance.create_surface(window.clone()).unwrap();
+        let adapter = wgpu_setup::create_adapter(
+            &instance,
+            Some(&surface),
+            backends,
+            app_config.force_fallback_adapter,
+        )?;
+        let (device, queue) = wgpu_setup::create_device(&adapter, app_config.optional_features());
+
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost_flag = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping or replacing the device is expected, ie during a rebuild
+            if matches!(
+                reason,
+                wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::Destroyed
+            ) {
+                println!("Device lost ({reason:?}): {message}");
+                lost_flag.store(true, Ordering::Relaxed);
+            }
+        });
         let surface_caps = surface.get_capabilities(&adapter);
-        let config = wgpu_setup::create_surface_config(size, surface_caps);
+        let present_modes = surface_caps.present_modes.clone();
+        let mut display = app_config.display_settings();
+        display.present_mode =
+            display::supported_present_mode(display.present_mode, &present_modes);
+        let config = wgpu_setup::create_surface_config(size, surface_caps, &display);
         surface.configure(&device, &config);
+        let surface = Arc::new(surface);
 
-        let virtual_gpu = VirtualGpu::new(device, queue, &config);
+        let mut virtual_gpu = VirtualGpu::new(device, queue, &config, display, app_config);
+        virtual_gpu.resize(config.width, config.height);
 
-        Self {
+        Ok(Self {
             surface,
             config,
-            window: window_arc,
-            camera_delta: Vec3A::ZERO,
-            camera_yaw_delta: 0.0,
+            window,
+            device_lost,
+            display,
+            present_modes,
 
             virtual_gpu,
-        }
+        })
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         self.config.width = new_size.width;
         self.config.height = new_size.height;
 
-        self.virtual_gpu
-            .frame_buffer
-            .adjust_scale(new_size.width, new_size.height);
+        self.virtual_gpu.resize(new_size.width, new_size.height);
 
+        self.virtual_gpu.wait_for_frames();
         self.surface
             .configure(&self.virtual_gpu.device, &self.config);
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture().unwrap();
-        let surface_view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-
-        self.virtual_gpu.render(&surface_view);
-        output.present();
-
-        Ok(())
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    /// Creates a fresh device & surface for the same window, and reloads
+    /// all resources from their original sources.
+    pub fn rebuild(self, app_config: &AppConfig) -> Result<Self, AdapterNotFound> {
+        let window = self.window;
+        // The old surface and render thread have to be gone before the window gets a new surface
+        let previous = self.virtual_gpu.into_previous();
+        drop(self.surface);
+
+        let mut state = State::new(window, app_config)?;
+        state.virtual_gpu.restore_resources(&previous);
+        Ok(state)
     }
 
-    fn update(&mut self) {
-        const DT: f32 = 1.0 / 60.0;
-        const CAMERA_SPEED: f32 = 2.5;
-        const CAMERA_ROT_SPEED: f32 = 0.75;
+    /// Applies any display settings changed by the game or hotkeys since the last frame.
+    pub fn apply_display_settings(&mut self) {
+        let mut settings = self.virtual_gpu.display;
+
+        // Grabbing doesn't need the surface reconfigured, so it's handled on its own
+        if settings.cursor_grab != self.display.cursor_grab {
+            display::grab_cursor(&self.window, settings.cursor_grab);
+            self.display.cursor_grab = settings.cursor_grab;
+        }
+
+        if settings == self.display {
+            return;
+        }
 
-        let forward = self.virtual_gpu.camera.get_forward();
-        let right = forward.cross(Vec3A::Y);
-        let up = Vec3A::Y;
+        if settings.window_mode != self.display.window_mode {
+            self.window
+                .set_fullscreen(settings.window_mode.fullscreen(&self.window));
+        }
 
-        self.virtual_gpu.camera.eye += forward * self.camera_delta.z * DT * CAMERA_SPEED;
-        self.virtual_gpu.camera.eye -= right * self.camera_delta.x * DT * CAMERA_SPEED;
-        self.virtual_gpu.camera.eye += up * self.camera_delta.y * DT * CAMERA_SPEED;
+        settings.present_mode =
+            display::supported_present_mode(settings.present_mode, &self.present_modes);
+        settings.frame_latency = settings.frame_latency.max(1);
 
-        self.virtual_gpu.camera.yaw -= self.camera_yaw_delta * DT * CAMERA_ROT_SPEED;
+        println!("Display settings: {settings:?}");
+        self.config.present_mode = settings.present_mode;
+        self.config.desired_maximum_frame_latency = settings.frame_latency;
+        self.virtual_gpu.wait_for_frames();
+        self.surface
+            .configure(&self.virtual_gpu.device, &self.config);
 
-        self.virtual_gpu.push_matrix(Mat4::IDENTITY);
-        self.virtual_gpu.set_texture(0);
+        self.virtual_gpu.display = settings;
+        self.display = settings;
+    }
+
+    /// Hands the recorded frame to the render thread. Frames drawn meanwhile
+    /// to an outdated or lost surface have it reconfigured, and timeouts skip
+    /// the frame, other errors are returned to the caller.
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let target = RenderTarget::Surface(self.surface.clone());
+        match self.virtual_gpu.render(target) {
+            Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                self.virtual_gpu.wait_for_frames();
+                self.surface
+                    .configure(&self.virtual_gpu.device, &self.config);
+                Ok(())
+            }
+            Err(wgpu::SurfaceError::Timeout) => Ok(()),
+            result => result,
+        }
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+}
+
+/// Built in environment hotkeys, applied before the game is updated.
+fn update_environment(virtual_gpu: &mut VirtualGpu, input: &InputState) {
+    let environment = &mut virtual_gpu.environment;
+    if input.action_pressed(actions::ENVIRONMENT_UP) {
+        environment.environment_color_strength.w += 0.1;
+    }
+    if input.action_pressed(actions::ENVIRONMENT_DOWN) {
+        environment.environment_color_strength.w -= 0.1;
     }
 }
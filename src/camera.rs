@@ -1,18 +1,94 @@
-use glam::{Mat4, Vec3A};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec3A, Vec4, Vec4Swizzles};
 use wgpu::SurfaceConfiguration;
 
+use crate::picking::Ray;
+
 pub type CameraUniformType = [f32; 52];
 
+/// How the 3d scene is projected. All projections use reverse-Z depth, with
+/// the near plane at 1 and far at 0, to match the depth test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Infinite perspective, using the camera's fov and near plane.
+    Perspective,
+    /// Parallel projection showing `height` world units vertically, ie for
+    /// isometric or CAD like views. Uses the camera's near plane.
+    #[allow(dead_code)]
+    Orthographic { height: f32, far: f32 },
+    /// Any other projection, ie oblique near planes or off-axis stereo.
+    /// The matrix should map near to 1 and far to 0 like the others.
+    #[allow(dead_code)]
+    Custom(Mat4),
+}
+
+/// The 3d camera, set by the game each frame. An identity orientation looks
+/// down -Z with Y up, and any pitch or roll can be applied on top.
 pub struct Camera {
-    pub eye: Vec3A,
-    pub yaw: f32,
-    up: Vec3A,
+    position: Vec3,
+    orientation: Quat,
     aspect: f32,
     fovy: f32,
     z_near: f32,
+    projection: Projection,
     width: u32,
     height: u32,
 
+    // The window size, and the part of it the frame buffer is letterboxed into
+    surface_size: Vec2,
+    scaling: Vec2,
+}
+
+/// What the shaders need of the camera for one frame, recorded with the
+/// frame and uploaded by the render thread.
+#[derive(Clone, Copy)]
+pub struct CameraFrame {
+    pub uniforms: CameraUniformType,
+    pub view: Mat4,
+    pub eye: Vec4,
+    pub projection: Mat4,
+}
+
+impl CameraFrame {
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.projection * self.view)
+    }
+}
+
+/// The planes bounding what the camera sees, in world space, facing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let x = view_projection.row(0);
+        let y = view_projection.row(1);
+        let z = view_projection.row(2);
+        let w = view_projection.row(3);
+
+        // Reverse-Z keeps depths between 0 at the far plane and w at the near plane.
+        // An infinite far plane has no normal, and is in front of every point
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.xyz().length();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        });
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.dot(center.extend(1.0)) >= -radius)
+    }
+}
+
+/// The gpu side of the camera, owned by the renderer.
+pub struct CameraBuffers {
     // Wgpu Stuff:
     pub buffer: wgpu::Buffer,
 
@@ -22,8 +98,8 @@ pub struct Camera {
     pub projections_buffer: wgpu::Buffer,
 }
 
-impl Camera {
-    pub fn new(device: &wgpu::Device, config: &SurfaceConfiguration) -> Self {
+impl CameraBuffers {
+    pub fn new(device: &wgpu::Device) -> Self {
         let views_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Views Buffer"),
             size: 8 * 1024 * 1024,
@@ -59,31 +135,212 @@ impl Camera {
         });
 
         Self {
-            eye: Vec3A::new(0.0, 1.0, 5.0),
-            yaw: 0.0,
-            up: Vec3A::Y,
+            buffer,
+            views_buffer,
+            positions_buffer,
+            projections_buffer,
+        }
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, frame: &CameraFrame) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&frame.uniforms));
+        queue.write_buffer(&self.views_buffer, 0, bytemuck::bytes_of(&frame.view));
+        queue.write_buffer(&self.positions_buffer, 0, bytemuck::bytes_of(&frame.eye));
+        queue.write_buffer(
+            &self.projections_buffer,
+            0,
+            bytemuck::bytes_of(&frame.projection),
+        );
+    }
+
+    pub fn size(&self) -> u64 {
+        self.buffer.size()
+            + self.views_buffer.size()
+            + self.positions_buffer.size()
+            + self.projections_buffer.size()
+    }
+}
+
+impl Camera {
+    pub fn new(config: &SurfaceConfiguration) -> Self {
+        Self {
+            position: Vec3::new(0.0, 1.0, 5.0),
+            orientation: Quat::IDENTITY,
             aspect: config.width as f32 / config.height as f32,
             fovy: 45.0,
             z_near: 0.1,
+            projection: Projection::Perspective,
             width: config.width,
             height: config.height,
-            buffer,
-            views_buffer,
-            positions_buffer,
-            projections_buffer,
+            surface_size: Vec2::new(config.width as f32, config.height as f32),
+            scaling: Vec2::ONE,
         }
     }
 
+    /// Copies the camera settings of a previous camera, ie after the device was lost.
+    pub fn restore(&mut self, previous: &Camera) {
+        self.position = previous.position;
+        self.orientation = previous.orientation;
+        self.fovy = previous.fovy;
+        self.z_near = previous.z_near;
+        self.projection = previous.projection;
+    }
+
+    /// Sets the position and orientation from a transform, any scale is ignored.
+    pub fn set_transform(&mut self, transform: Mat4) {
+        let (_, orientation, position) = transform.to_scale_rotation_translation();
+        self.set(position, orientation);
+    }
+
+    pub fn set(&mut self, position: Vec3, orientation: Quat) {
+        self.position = position;
+        self.orientation = orientation.normalize();
+    }
+
+    pub fn look_at(&mut self, eye: Vec3, target: Vec3, up: Vec3) {
+        let view = Mat4::look_at_rh(eye, target, up);
+        let orientation = Quat::from_mat4(&view).inverse();
+        self.set(eye, orientation);
+    }
+
+    /// Vertical field of view in degrees, used by the perspective projection.
+    pub fn set_fov(&mut self, fovy: f32) {
+        self.fovy = fovy;
+    }
+
+    /// Near plane distance, used by the perspective and orthographic projections.
+    pub fn set_near(&mut self, z_near: f32) {
+        self.z_near = z_near;
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    #[allow(dead_code)]
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    #[allow(dead_code)]
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.orientation, self.position)
+    }
+
+    #[allow(dead_code)]
+    pub fn orientation(&self) -> Quat {
+        self.orientation
+    }
+
+    pub fn eye(&self) -> Vec3A {
+        self.position.into()
+    }
+
     pub fn get_forward(&self) -> Vec3A {
-        Vec3A::new(self.yaw.sin(), 0.0, -self.yaw.cos())
+        (self.orientation * Vec3A::NEG_Z).normalize()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_right(&self) -> Vec3A {
+        (self.orientation * Vec3A::X).normalize()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_up(&self) -> Vec3A {
+        (self.orientation * Vec3A::Y).normalize()
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    #[allow(dead_code)]
+    pub fn fov(&self) -> f32 {
+        self.fovy
+    }
+
+    #[allow(dead_code)]
+    pub fn near(&self) -> f32 {
+        self.z_near
     }
 
     pub fn get_view(&self) -> Mat4 {
-        Mat4::look_to_rh(self.eye.into(), self.get_forward().into(), self.up.into())
+        Mat4::from_quat(self.orientation.inverse()) * Mat4::from_translation(-self.position)
     }
 
     pub fn get_projection_3d(&self) -> Mat4 {
-        Mat4::perspective_infinite_reverse_rh(self.fovy.to_radians(), self.aspect, self.z_near)
+        match self.projection {
+            Projection::Perspective => Mat4::perspective_infinite_reverse_rh(
+                self.fovy.to_radians(),
+                self.aspect,
+                self.z_near,
+            ),
+            Projection::Orthographic { height, far } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                // Near and far are swapped for reverse-Z
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    far,
+                    self.z_near,
+                )
+            }
+            Projection::Custom(projection) => projection,
+        }
+    }
+
+    /// Called when the window is resized, `scaling` is the frame buffer's
+    /// letterboxed size as a fraction of the window.
+    pub fn set_surface(&mut self, width: u32, height: u32, scaling: [f32; 2]) {
+        self.surface_size = Vec2::new(width as f32, height as f32);
+        self.scaling = Vec2::from(scaling);
+    }
+
+    /// Converts a window pixel to the frame buffer's normalized device
+    /// coordinates, or None if it's in the letterbox bars.
+    pub fn screen_to_ndc(&self, x: f32, y: f32) -> Option<Vec2> {
+        let surface_ndc = Vec2::new(
+            x / self.surface_size.x * 2.0 - 1.0,
+            1.0 - y / self.surface_size.y * 2.0,
+        );
+        let ndc = surface_ndc / self.scaling;
+        (ndc.abs().max_element() <= 1.0).then_some(ndc)
+    }
+
+    /// Returns the ray through a window pixel, ie the mouse position. Returns
+    /// None for pixels in the letterbox bars around the frame buffer.
+    pub fn screen_to_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        let ndc = self.screen_to_ndc(x, y)?;
+        let inverse = (self.get_projection_3d() * self.get_view()).inverse();
+
+        // Reverse-Z puts the near plane at 1, and the far plane at 0
+        let near = inverse * ndc.extend(1.0).extend(1.0);
+        let far = inverse * ndc.extend(0.0).extend(1.0);
+
+        let origin = near.xyz() / near.w;
+        // An infinite far plane unprojects to a direction rather than a point
+        let direction = if far.w.abs() < f32::EPSILON {
+            far.xyz()
+        } else {
+            far.xyz() / far.w - origin
+        };
+
+        Some(Ray {
+            origin,
+            direction: direction.normalize(),
+        })
+    }
+
+    pub fn frame(&self) -> CameraFrame {
+        CameraFrame {
+            uniforms: self.get_camera_uniforms(),
+            view: self.get_view(),
+            eye: self.eye().extend(1.0),
+            projection: self.get_projection_3d(),
+        }
     }
 
     pub fn get_camera_uniforms(&self) -> CameraUniformType {
@@ -96,11 +353,118 @@ impl Camera {
         view.write_cols_to_slice(&mut out[0..16]);
         projection.write_cols_to_slice(&mut out[16..32]);
         ortho.write_cols_to_slice(&mut out[32..48]);
-        self.eye.write_to_slice(&mut out[48..52]);
+        self.eye().extend(1.0).write_to_slice(&mut out[48..52]);
         out
     }
 
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.get_projection_3d() * self.get_view())
+    }
+
     pub fn get_projection_2d(&self) -> Mat4 {
         Mat4::orthographic_rh(0.0, self.width as f32, self.height as f32, 0.0, 1.0, -1.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(width: u32, height: u32) -> Camera {
+        Camera::new(&SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::AutoVsync,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        })
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+    }
+
+    // Projects a world position back to the frame buffer's ndc
+    fn project(camera: &Camera, position: Vec3) -> Vec2 {
+        let clip = camera.get_projection_3d() * camera.get_view() * position.extend(1.0);
+        clip.xy() / clip.w
+    }
+
+    #[test]
+    fn screen_centre_looks_forward() {
+        let mut camera = camera(800, 600);
+        camera.look_at(
+            Vec3::new(3.0, 2.0, 1.0),
+            Vec3::new(-1.0, 0.0, -4.0),
+            Vec3::Y,
+        );
+
+        let ray = camera.screen_to_ray(400.0, 300.0).unwrap();
+        assert_near(ray.direction, camera.get_forward().into());
+        // Rays start on the near plane, in front of the eye
+        assert_near(
+            ray.origin,
+            Vec3::from(camera.eye() + camera.get_forward() * camera.near()),
+        );
+    }
+
+    #[test]
+    fn rays_unproject_through_their_pixel() {
+        let mut camera = camera(800, 600);
+        camera.look_at(Vec3::new(0.0, 1.0, 5.0), Vec3::ZERO, Vec3::Y);
+
+        for (x, y) in [(0.0, 0.0), (800.0, 600.0), (200.0, 450.0)] {
+            let ray = camera.screen_to_ray(x, y).unwrap();
+            let ndc = camera.screen_to_ndc(x, y).unwrap();
+            // Points far along the ray stay on the pixel, with the infinite far plane
+            for distance in [1.0, 100.0, 10000.0] {
+                let projected = project(&camera, ray.at(distance));
+                assert!(projected.abs_diff_eq(ndc, 1e-3), "{projected} != {ndc}");
+            }
+        }
+    }
+
+    #[test]
+    fn letterbox_bars_have_no_ray() {
+        // The frame buffer fills the height, and 80% of the width in the middle
+        let mut camera = camera(800, 600);
+        camera.set_surface(1000, 600, [0.8, 1.0]);
+
+        assert!(camera.screen_to_ray(50.0, 300.0).is_none());
+        assert!(camera.screen_to_ray(950.0, 300.0).is_none());
+
+        // The frame buffer's left edge is the left edge of the frustum
+        let ray = camera.screen_to_ray(100.0, 300.0).unwrap();
+        assert_eq!(
+            camera.screen_to_ndc(100.0, 300.0),
+            Some(Vec2::new(-1.0, 0.0))
+        );
+        let half_width = (camera.fov().to_radians() / 2.0).tan() * camera.aspect();
+        assert!((ray.direction.x / ray.direction.z - half_width).abs() < 1e-4);
+
+        let centre = camera.screen_to_ray(500.0, 300.0).unwrap();
+        assert_near(centre.direction, camera.get_forward().into());
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut camera = camera(800, 600);
+        camera.set_projection(Projection::Orthographic {
+            height: 10.0,
+            far: 100.0,
+        });
+
+        for (x, y) in [(0.0, 0.0), (400.0, 300.0), (800.0, 600.0)] {
+            let ray = camera.screen_to_ray(x, y).unwrap();
+            assert_near(ray.direction, camera.get_forward().into());
+        }
+        let corner = camera.screen_to_ray(800.0, 0.0).unwrap();
+        assert_near(
+            corner.origin,
+            Vec3::from(camera.eye()) + Vec3::new(5.0 * camera.aspect(), 5.0, -camera.near()),
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::f32::consts::FRAC_PI_2;
+
+use glam::{Quat, Vec3};
+
+use crate::{
+    contexts::Draw3dContext,
+    input::{actions, InputState},
+    time::GameTime,
+};
+
+// Radians per pixel of mouse movement
+const MOUSE_SENSITIVITY: f32 = 0.003;
+// Stops pitch from flipping over the poles
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Free flying camera. Moves with the default movement bindings relative to
+/// where it's looking, and looks around with the mouse while the mouse look
+/// action is held, grabbing the cursor.
+///
+/// All controllers keep the previous tick's state, so drawing can interpolate.
+pub struct FreeFlyController {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub move_speed: f32,
+    pub turn_speed: f32,
+
+    looking: bool,
+    previous_position: Vec3,
+    previous_orientation: Quat,
+}
+
+impl FreeFlyController {
+    pub fn new(position: Vec3, yaw: f32, pitch: f32) -> Self {
+        let mut controller = Self {
+            position,
+            yaw,
+            pitch,
+            move_speed: 2.5,
+            turn_speed: 0.75,
+            looking: false,
+            previous_position: position,
+            previous_orientation: Quat::IDENTITY,
+        };
+        controller.previous_orientation = controller.orientation();
+        controller
+    }
+
+    /// Yaw turns clockwise when seen from above, and positive pitch looks up.
+    pub fn orientation(&self) -> Quat {
+        Quat::from_rotation_y(-self.yaw) * Quat::from_rotation_x(self.pitch)
+    }
+
+    pub fn update(&mut self, input: &InputState, time: &GameTime) {
+        self.previous_position = self.position;
+        self.previous_orientation = self.orientation();
+
+        self.looking = input.action_down(actions::MOUSE_LOOK);
+        if self.looking {
+            let delta = input.mouse_delta() * MOUSE_SENSITIVITY;
+            self.yaw += delta.x;
+            self.pitch = (self.pitch - delta.y).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        let turn = input.action_axis(actions::TURN_LEFT, actions::TURN_RIGHT);
+        self.yaw += turn * time.dt * self.turn_speed;
+
+        let orientation = self.orientation();
+        let forward = orientation * Vec3::NEG_Z;
+        let right = orientation * Vec3::X;
+
+        let move_forward = input.action_axis(actions::MOVE_BACK, actions::MOVE_FORWARD);
+        let move_right = input.action_axis(actions::MOVE_LEFT, actions::MOVE_RIGHT);
+        let move_up = input.action_axis(actions::MOVE_DOWN, actions::MOVE_UP);
+
+        let step = time.dt * self.move_speed;
+        self.position += forward * move_forward * step;
+        self.position += right * move_right * step;
+        self.position += Vec3::Y * move_up * step;
+    }
+
+    /// Sets the camera, `alpha` of the way between the last two updates.
+    pub fn apply(&self, gpu: &mut dyn Draw3dContext, alpha: f32) {
+        let position = self.previous_position.lerp(self.position, alpha);
+        let orientation = self.previous_orientation.slerp(self.orientation(), alpha);
+        gpu.set_camera(position, orientation);
+        gpu.set_cursor_grab(self.looking);
+    }
+}
+
+/// Turntable camera circling a target. Dragging with the mouse look action
+/// or the turn bindings rotate it, scrolling or moving forward & back zooms,
+/// and the other movement bindings pan the target.
+pub struct OrbitController {
+    pub target: Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Keeps turning at this many radians per second, 0 to disable.
+    pub auto_rotate: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub pan_speed: f32,
+
+    looking: bool,
+    previous_target: Vec3,
+    previous_position: Vec3,
+    previous_orientation: Quat,
+}
+
+impl OrbitController {
+    pub fn new(target: Vec3, distance: f32) -> Self {
+        let mut controller = Self {
+            target,
+            distance,
+            yaw: 0.0,
+            pitch: -0.3,
+            auto_rotate: 0.0,
+            min_distance: 0.5,
+            max_distance: 100.0,
+            pan_speed: 2.5,
+            looking: false,
+            previous_target: target,
+            previous_position: Vec3::ZERO,
+            previous_orientation: Quat::IDENTITY,
+        };
+        controller.previous_position = controller.position();
+        controller.previous_orientation = controller.orientation();
+        controller
+    }
+
+    pub fn orientation(&self) -> Quat {
+        Quat::from_rotation_y(-self.yaw) * Quat::from_rotation_x(self.pitch)
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.target - self.orientation() * Vec3::NEG_Z * self.distance
+    }
+
+    pub fn update(&mut self, input: &InputState, time: &GameTime) {
+        const ZOOM_STEP: f32 = 1.1;
+        const ZOOM_SPEED: f32 = 2.0;
+
+        self.previous_target = self.target;
+        self.previous_position = self.position();
+        self.previous_orientation = self.orientation();
+
+        self.looking = input.action_down(actions::MOUSE_LOOK);
+        if self.looking {
+            let delta = input.mouse_delta() * MOUSE_SENSITIVITY;
+            self.yaw += delta.x;
+            self.pitch = (self.pitch - delta.y).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        let right = self.orientation() * Vec3::X;
+        let pan_right = input.action_axis(actions::MOVE_LEFT, actions::MOVE_RIGHT);
+        let pan_up = input.action_axis(actions::MOVE_DOWN, actions::MOVE_UP);
+        self.target += (right * pan_right + Vec3::Y * pan_up) * time.dt * self.pan_speed;
+
+        let turn = input.action_axis(actions::TURN_LEFT, actions::TURN_RIGHT);
+        self.yaw += (turn + self.auto_rotate) * time.dt;
+
+        let zoom = input.action_axis(actions::MOVE_FORWARD, actions::MOVE_BACK);
+        self.distance *= ZOOM_STEP.powf(-input.scroll_delta().y);
+        self.distance *= ZOOM_SPEED.powf(zoom * time.dt);
+        self.distance = self.distance.clamp(self.min_distance, self.max_distance);
+    }
+
+    pub fn apply(&self, gpu: &mut dyn Draw3dContext, alpha: f32) {
+        // Interpolating the orbit itself keeps the target centered while turning
+        let orientation = self.previous_orientation.slerp(self.orientation(), alpha);
+        let target = self.previous_target.lerp(self.target, alpha);
+        let previous_distance = (self.previous_position - self.previous_target).length();
+        let distance = previous_distance + (self.distance - previous_distance) * alpha;
+        let position = target - orientation * Vec3::NEG_Z * distance;
+
+        gpu.set_camera(position, orientation);
+        gpu.set_cursor_grab(self.looking);
+    }
+}
+
+/// Follows behind a moving target, easing towards it rather than snapping.
+/// The game passes in the target each update, ie a player or vehicle.
+pub struct FollowController {
+    /// Where the camera sits relative to the target, in the target's space.
+    pub offset: Vec3,
+    /// Point looked at, relative to the target's position.
+    pub look_offset: Vec3,
+    /// How quickly the camera catches up, higher is stiffer.
+    pub stiffness: f32,
+
+    position: Vec3,
+    look_target: Vec3,
+    previous_position: Vec3,
+    previous_look_target: Vec3,
+}
+
+impl FollowController {
+    #[allow(dead_code)]
+    pub fn new(offset: Vec3, stiffness: f32) -> Self {
+        Self {
+            offset,
+            look_offset: Vec3::ZERO,
+            stiffness,
+            position: offset,
+            look_target: Vec3::ZERO,
+            previous_position: offset,
+            previous_look_target: Vec3::ZERO,
+        }
+    }
+
+    /// Jumps straight to the target, ie when it teleports or on the first frame.
+    #[allow(dead_code)]
+    pub fn snap(&mut self, target: Vec3, rotation: Quat) {
+        self.position = target + rotation * self.offset;
+        self.look_target = target + self.look_offset;
+        self.previous_position = self.position;
+        self.previous_look_target = self.look_target;
+    }
+
+    #[allow(dead_code)]
+    pub fn update(&mut self, target: Vec3, rotation: Quat, time: &GameTime) {
+        self.previous_position = self.position;
+        self.previous_look_target = self.look_target;
+
+        // Framerate independent exponential smoothing
+        let t = 1.0 - (-self.stiffness * time.dt).exp();
+        self.position = self.position.lerp(target + rotation * self.offset, t);
+        self.look_target = self.look_target.lerp(target + self.look_offset, t);
+    }
+
+    #[allow(dead_code)]
+    pub fn apply(&self, gpu: &mut dyn Draw3dContext, alpha: f32) {
+        let position = self.previous_position.lerp(self.position, alpha);
+        let look_target = self.previous_look_target.lerp(self.look_target, alpha);
+        gpu.look_at(position, look_target, Vec3::Y);
+    }
+}
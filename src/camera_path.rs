@@ -0,0 +1,434 @@
+use std::fs;
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
+
+use glam::{Mat4, Quat, Vec3};
+use serde::Deserialize;
+
+use crate::{contexts::Draw3dContext, pipeline::Pipeline, time::GameTime};
+
+const DEFAULT_FOV: f32 = 45.0;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// Passes smoothly through every keyframe, with no extra controls.
+    #[default]
+    CatmullRom,
+    /// Like Catmull-Rom, but keyframes can set their own handles to shape the curve.
+    Bezier,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub position: Vec3,
+    pub orientation: Quat,
+    pub fov: f32,
+    /// Bezier handles, relative to the position. Derived from the neighbours when unset.
+    pub handle_in: Option<Vec3>,
+    pub handle_out: Option<Vec3>,
+}
+
+/// Where the camera is at a point along a path.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraPose {
+    pub position: Vec3,
+    pub orientation: Quat,
+    pub fov: f32,
+}
+
+/// A keyframe as written in a path file. Orientation can be given directly
+/// as a quaternion, or as a point to look at.
+#[derive(Deserialize)]
+struct KeyframeFile {
+    time: f32,
+    position: Vec3,
+    orientation: Option<Quat>,
+    target: Option<Vec3>,
+    fov: Option<f32>,
+    handle_in: Option<Vec3>,
+    handle_out: Option<Vec3>,
+}
+
+#[derive(Deserialize)]
+struct CameraPathFile {
+    #[serde(default)]
+    interpolation: Interpolation,
+    #[serde(default)]
+    looping: bool,
+    keyframes: Vec<KeyframeFile>,
+}
+
+/// Keyframed camera movement, ie for attract modes and cutscenes.
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+    /// Starts over once the end is reached. Ending on the first keyframe makes it seamless.
+    pub looping: bool,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<Keyframe>, interpolation: Interpolation) -> Self {
+        if keyframes.is_empty() {
+            panic!("Camera paths need at least one keyframe");
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self {
+            keyframes,
+            interpolation,
+            looping: false,
+        }
+    }
+
+    /// Loads a path from a toml file, see `assets/camera paths` for examples.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let file: CameraPathFile = match fs::read_to_string(path) {
+            Ok(contents) => match toml::from_str(&contents) {
+                Ok(file) => file,
+                Err(e) => panic!("Invalid camera path {}: {e}", path.display()),
+            },
+            Err(e) => panic!("Failed to read camera path {}: {e}", path.display()),
+        };
+
+        let keyframes = file
+            .keyframes
+            .into_iter()
+            .map(|key| {
+                let orientation = match (key.orientation, key.target) {
+                    (Some(orientation), _) => orientation.normalize(),
+                    (None, Some(target)) => {
+                        Quat::from_mat4(&Mat4::look_at_rh(key.position, target, Vec3::Y)).inverse()
+                    }
+                    (None, None) => Quat::IDENTITY,
+                };
+
+                Keyframe {
+                    time: key.time,
+                    position: key.position,
+                    orientation,
+                    fov: key.fov.unwrap_or(DEFAULT_FOV),
+                    handle_in: key.handle_in,
+                    handle_out: key.handle_out,
+                }
+            })
+            .collect();
+
+        let mut camera_path = Self::new(keyframes, file.interpolation);
+        camera_path.looping = file.looping;
+        camera_path
+    }
+
+    pub fn start_time(&self) -> f32 {
+        self.keyframes[0].time
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes[self.keyframes.len() - 1].time - self.start_time()
+    }
+
+    pub fn sample(&self, time: f32) -> CameraPose {
+        let keys = &self.keyframes;
+        let duration = self.duration();
+
+        let mut time = time - self.start_time();
+        if self.looping && duration > 0.0 && !(0.0..=duration).contains(&time) {
+            time = time.rem_euclid(duration);
+        }
+        let time = time.clamp(0.0, duration) + self.start_time();
+
+        // The segment between keys i and i + 1 containing the time
+        let i = keys
+            .iter()
+            .rposition(|key| key.time <= time)
+            .unwrap_or(0)
+            .min(keys.len().saturating_sub(2));
+        let next = (i + 1).min(keys.len() - 1);
+
+        // A looping path ends on its first keyframe, so the neighbours wrap past
+        // that joining keyframe rather than stopping at the ends
+        let last = keys.len() - 1;
+        let (before, after) = if self.looping && last > 1 {
+            (
+                if i == 0 { last - 1 } else { i - 1 },
+                if next == last { 1 } else { next + 1 },
+            )
+        } else {
+            (i.saturating_sub(1), (next + 1).min(last))
+        };
+
+        let k1 = &keys[i];
+        let k2 = &keys[next];
+        let k0 = &keys[before];
+        let k3 = &keys[after];
+
+        let span = k2.time - k1.time;
+        let u = if span > 0.0 {
+            ((time - k1.time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let position = match self.interpolation {
+            Interpolation::CatmullRom => {
+                catmull_rom(k0.position, k1.position, k2.position, k3.position, u)
+            }
+            Interpolation::Bezier => {
+                // Default handles give the same curve as Catmull-Rom
+                let out = k1.handle_out.unwrap_or((k2.position - k0.position) / 6.0);
+                let into = k2.handle_in.unwrap_or((k1.position - k3.position) / 6.0);
+                bezier(
+                    k1.position,
+                    k1.position + out,
+                    k2.position + into,
+                    k2.position,
+                    u,
+                )
+            }
+        };
+
+        CameraPose {
+            position,
+            orientation: k1.orientation.slerp(k2.orientation, u),
+            fov: catmull_rom(k0.fov, k1.fov, k2.fov, k3.fov, u),
+        }
+    }
+
+    /// Draws the path as a line, with a frustum at every keyframe showing
+    /// where the camera looks. Uses the current camera to face the lines.
+    pub fn draw_preview(&self, gpu: &mut dyn Draw3dContext) {
+        const SAMPLES_PER_KEY: usize = 16;
+        const LINE_WIDTH: f32 = 0.02;
+        const FRUSTUM_DEPTH: f32 = 0.5;
+        const PATH_COLOR: Vec3 = Vec3::new(1.0, 0.8, 0.1);
+        const FRUSTUM_COLOR: Vec3 = Vec3::new(0.1, 0.8, 1.0);
+
+        let camera = gpu.get_camera();
+        let eye = Vec3::from(camera.eye());
+        let aspect = camera.aspect();
+
+        let mut lines = Lines {
+            data: Vec::new(),
+            eye,
+            width: LINE_WIDTH,
+        };
+
+        let samples = SAMPLES_PER_KEY * self.keyframes.len();
+        let start = self.start_time();
+        let duration = self.duration();
+        let mut previous = self.sample(start).position;
+        for n in 1..=samples {
+            let position = self
+                .sample(start + duration * n as f32 / samples as f32)
+                .position;
+            lines.push(previous, position, PATH_COLOR);
+            previous = position;
+        }
+
+        for key in self.keyframes.iter() {
+            let half_height = (key.fov.to_radians() / 2.0).tan() * FRUSTUM_DEPTH;
+            let half_width = half_height * aspect;
+
+            let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| {
+                key.position
+                    + key.orientation * Vec3::new(x * half_width, y * half_height, -FRUSTUM_DEPTH)
+            });
+
+            for (n, corner) in corners.iter().enumerate() {
+                lines.push(key.position, *corner, FRUSTUM_COLOR);
+                lines.push(*corner, corners[(n + 1) % corners.len()], FRUSTUM_COLOR);
+            }
+        }
+
+        gpu.push_matrix(Mat4::IDENTITY);
+        gpu.draw_tri_list(&lines.data, Pipeline::Color);
+    }
+}
+
+/// Plays a path back on the camera. Time advances with the game ticks,
+/// so playback is deterministic with recorded input.
+pub struct CameraPathPlayer {
+    pub path: CameraPath,
+    pub playing: bool,
+    time: f32,
+    previous_time: f32,
+}
+
+impl CameraPathPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        let time = path.start_time();
+        Self {
+            path,
+            playing: false,
+            time,
+            previous_time: time,
+        }
+    }
+
+    pub fn play(&mut self) {
+        if self.is_finished() {
+            self.seek(self.path.start_time());
+        }
+        self.playing = true;
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+        self.previous_time = time;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.path.looping && self.time >= self.path.start_time() + self.path.duration()
+    }
+
+    pub fn update(&mut self, time: &GameTime) {
+        self.previous_time = self.time;
+        if self.playing {
+            self.time += time.dt;
+            if self.is_finished() {
+                self.playing = false;
+            }
+        }
+    }
+
+    pub fn apply(&self, gpu: &mut dyn Draw3dContext, alpha: f32) {
+        let pose = self
+            .path
+            .sample(self.previous_time + (self.time - self.previous_time) * alpha);
+        gpu.set_camera(pose.position, pose.orientation);
+        gpu.set_fov(pose.fov);
+    }
+}
+
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, u: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let u2 = u * u;
+    let u3 = u2 * u;
+
+    (p1 * 2.0
+        + (p2 - p0) * u
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * u2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * u3)
+        * 0.5
+}
+
+fn bezier(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, u: f32) -> Vec3 {
+    let v = 1.0 - u;
+    p0 * (v * v * v) + p1 * (3.0 * v * v * u) + p2 * (3.0 * v * u * u) + p3 * (u * u * u)
+}
+
+// Builds lines out of thin quads facing the eye, as there's no line pipeline
+struct Lines {
+    data: Vec<f32>,
+    eye: Vec3,
+    width: f32,
+}
+
+impl Lines {
+    fn push(&mut self, a: Vec3, b: Vec3, color: Vec3) {
+        let direction = b - a;
+        let to_eye = self.eye - (a + b) / 2.0;
+        // Wound counter clockwise when seen from the eye, so they survive back face culling
+        let side = to_eye.cross(direction).normalize_or_zero() * (self.width / 2.0);
+
+        for position in [a - side, b - side, b + side, a - side, b + side, a + side] {
+            self.data.extend_from_slice(&position.to_array());
+            self.data.extend_from_slice(&color.to_array());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, position: Vec3, fov: f32) -> Keyframe {
+        Keyframe {
+            time,
+            position,
+            orientation: Quat::from_rotation_y(time),
+            fov,
+            handle_in: None,
+            handle_out: None,
+        }
+    }
+
+    // A square loop, ending back on the first keyframe
+    fn square(interpolation: Interpolation) -> CameraPath {
+        CameraPath::new(
+            vec![
+                keyframe(1.0, Vec3::new(0.0, 0.0, 0.0), 45.0),
+                keyframe(2.0, Vec3::new(4.0, 0.0, 0.0), 60.0),
+                keyframe(3.0, Vec3::new(4.0, 0.0, 4.0), 30.0),
+                keyframe(4.0, Vec3::new(0.0, 0.0, 4.0), 50.0),
+                keyframe(5.0, Vec3::new(0.0, 0.0, 0.0), 45.0),
+            ],
+            interpolation,
+        )
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+    }
+
+    #[test]
+    fn keyframe_times_sample_keyframes() {
+        for interpolation in [Interpolation::CatmullRom, Interpolation::Bezier] {
+            for looping in [false, true] {
+                let mut path = square(interpolation);
+                path.looping = looping;
+                for key in path.keyframes.iter() {
+                    let pose = path.sample(key.time);
+                    assert_near(pose.position, key.position);
+                    assert!(pose.orientation.abs_diff_eq(key.orientation, 1e-5));
+                    assert!((pose.fov - key.fov).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn default_bezier_handles_match_catmull_rom() {
+        for looping in [false, true] {
+            let mut catmull_rom = square(Interpolation::CatmullRom);
+            let mut bezier = square(Interpolation::Bezier);
+            catmull_rom.looping = looping;
+            bezier.looping = looping;
+            for n in 0..=40 {
+                let time = 1.0 + n as f32 / 10.0;
+                assert_near(
+                    bezier.sample(time).position,
+                    catmull_rom.sample(time).position,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn looping_path_wraps() {
+        let mut path = square(Interpolation::CatmullRom);
+        path.looping = true;
+
+        for time in [1.25, 2.5, 4.75] {
+            let position = path.sample(time).position;
+            assert_near(path.sample(time + path.duration()).position, position);
+            assert_near(path.sample(time - path.duration()).position, position);
+        }
+
+        // The curve keeps its direction through the joining keyframe
+        const STEP: f32 = 0.01;
+        let end = path.start_time() + path.duration();
+        let before = path.sample(end).position - path.sample(end - STEP).position;
+        let after = path.sample(end + STEP).position - path.sample(end).position;
+        assert!(before.normalize().dot(after.normalize()) > 0.999);
+        assert!(before.length() > 0.0);
+
+        // Without looping, the ends hold still
+        path.looping = false;
+        assert_near(path.sample(end + 1.0).position, path.keyframes[4].position);
+    }
+}
@@ -0,0 +1,119 @@
+use glam::Mat4;
+
+use crate::{
+    contexts::Record3dContext,
+    lights::Light,
+    picking::{DrawnMesh, MeshHandle},
+    pipeline::Pipeline,
+    virtual_render_pass::Command,
+};
+
+/// Draws recorded away from the virtual gpu, ie on a worker thread, with
+/// their own copies of the immediate, instance and light data. Lists are
+/// appended to the frame with `append_command_list`, in the order they're
+/// appended no matter which finished recording first.
+///
+/// Lists start from whatever pipeline, textures and matrix the frame was
+/// left with when they're appended, so they should set their own.
+#[derive(Clone, Default)]
+pub struct CommandList {
+    pub commands: Vec<Command>,
+    pub immediate_data: Vec<f32>,
+    pub matrices: Vec<Mat4>,
+    // One per matrix, like the object id buffer
+    pub object_ids: Vec<u32>,
+    // In world space, moved into view space when appended
+    pub lights: Vec<Light>,
+    pub markers: Vec<&'static str>,
+    // Instances are relative to the list's first matrix
+    pub drawn_meshes: Vec<DrawnMesh>,
+
+    object_id: u32,
+}
+
+impl CommandList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Empties the list, keeping its allocations for the next frame.
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        self.commands.clear();
+        self.immediate_data.clear();
+        self.matrices.clear();
+        self.object_ids.clear();
+        self.lights.clear();
+        self.markers.clear();
+        self.drawn_meshes.clear();
+        self.object_id = 0;
+    }
+
+    fn push_drawn_mesh(&mut self, mesh: MeshHandle) {
+        let Some(matrix) = self.matrices.last() else {
+            return;
+        };
+        self.drawn_meshes.push(DrawnMesh {
+            mesh,
+            instance: self.matrices.len() as u32 - 1,
+            matrix: *matrix,
+        });
+    }
+}
+
+impl Record3dContext for CommandList {
+    // Markers past the frame's limit are dropped when the list is appended
+    fn gpu_marker(&mut self, label: &'static str) {
+        self.commands.push(Command::GpuMarker(self.markers.len()));
+        self.markers.push(label);
+    }
+
+    fn set_object_id(&mut self, id: u32) {
+        self.object_id = id;
+    }
+
+    fn draw_tri_list(&mut self, data: &[f32], pipeline: Pipeline) {
+        let attribute_count = pipeline.get_attribute_count();
+        if !data.len().is_multiple_of(attribute_count) {
+            println!("Invalid triangle list, size mismatch");
+            return;
+        }
+
+        self.immediate_data.extend_from_slice(data);
+        self.commands.push(Command::SetPipeline(pipeline));
+        self.commands
+            .push(Command::Draw((data.len() / attribute_count) as u32));
+    }
+
+    fn push_light(&mut self, light: &Light) {
+        self.lights.push(*light);
+    }
+
+    fn push_matrix(&mut self, matrix: Mat4) {
+        self.commands.push(Command::SetModelMatrix);
+        self.matrices.push(matrix);
+        self.object_ids.push(self.object_id);
+    }
+
+    fn draw_static_mesh(&mut self, index: usize) {
+        self.commands.push(Command::DrawStaticMesh(index));
+        self.push_drawn_mesh(MeshHandle::Static(index));
+    }
+
+    fn draw_static_mesh_indexed(&mut self, index: usize) {
+        self.commands.push(Command::DrawStaticMeshIndexed(index));
+        self.push_drawn_mesh(MeshHandle::Indexed(index));
+    }
+
+    fn draw_sprite(&mut self, index: usize) {
+        self.commands.push(Command::DrawSprite(index));
+    }
+
+    fn set_texture(&mut self, tex_id: usize) {
+        self.commands.push(Command::SetTexture(tex_id));
+    }
+
+    fn set_matcap(&mut self, matcap_id: usize) {
+        self.commands.push(Command::SetMatcap(matcap_id));
+    }
+}
@@ -0,0 +1,398 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::display::{DisplaySettings, WindowMode};
+use crate::gpu_culling;
+use crate::gpu_timer;
+use crate::indirect_draws;
+use crate::resolution::Resolution;
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+// Lower tick rates are clamped, a rate of 0 or less would never update
+const MIN_TICK_RATE: f32 = 1.0;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub resolution: Resolution,
+    /// Game updates per second, rendering runs as fast as the present mode allows.
+    pub tick_rate: f32,
+    /// Most updates run in a single frame when catching up, any further time is dropped.
+    pub max_ticks_per_frame: u32,
+    pub window_mode: WindowMode,
+    pub vsync: Vsync,
+    pub frame_latency: u32,
+    /// Recorded frames that can wait on the render thread, 1 renders each frame
+    /// before the next is simulated.
+    pub frames_in_flight: u32,
+    pub backend: Backend,
+    /// Forces a software adapter, ie for machines without a gpu.
+    pub force_fallback_adapter: bool,
+    pub asset_root: PathBuf,
+
+    /// Right, Left, Top, Bottom, Front, Back. Relative to the asset root.
+    pub skybox: [String; 6],
+
+    /// Bound for matcap pipelines until the game sets its own. Relative to the asset root.
+    pub default_matcap: String,
+
+    /// Records every frame's input to this file.
+    pub record: Option<PathBuf>,
+    /// Plays back input recorded with `record`, live input is ignored until it ends.
+    pub playback: Option<PathBuf>,
+    /// Renders offscreen without a window, ie for scripted playthroughs.
+    pub headless: bool,
+    /// Exits after this many updates.
+    pub frames: Option<u64>,
+
+    /// Keeps mesh positions on the cpu, so meshes can be picked with rays.
+    pub cpu_picking: bool,
+
+    /// Renders an object id attachment in the main pass, for pixel perfect picking.
+    pub object_id_picking: bool,
+
+    /// Shows the frame stats overlay on startup, it's toggled with F3.
+    pub stats_overlay: bool,
+
+    /// Times the passes on the gpu, where timestamp queries are supported.
+    pub gpu_timing: bool,
+
+    /// Draws indexed static meshes from an indirect buffer, where supported.
+    pub indirect_draws: bool,
+
+    /// Culls indirect draws against the camera frustum in a compute pass.
+    pub gpu_culling: bool,
+
+    /// Skips static mesh draws outside the camera frustum as they're recorded.
+    pub cpu_culling: bool,
+
+    /// Reorders static mesh draws by pipeline, texture and mesh before they're encoded.
+    pub sort_draws: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            resolution: Resolution::Full,
+            tick_rate: 60.0,
+            max_ticks_per_frame: 5,
+            window_mode: WindowMode::Windowed,
+            vsync: Vsync::Auto,
+            frame_latency: 2,
+            frames_in_flight: 2,
+            backend: Backend::Primary,
+            force_fallback_adapter: false,
+            asset_root: PathBuf::from("assets"),
+            skybox: [
+                "skybox3/right.png".to_string(),
+                "skybox3/left.png".to_string(),
+                "skybox3/top.png".to_string(),
+                "skybox3/bottom.png".to_string(),
+                "skybox3/front.png".to_string(),
+                "skybox3/back.png".to_string(),
+            ],
+            default_matcap: "matcaps/0A0A0A_A9A9A9_525252_747474-128px.png".to_string(),
+            record: None,
+            playback: None,
+            headless: false,
+            frames: None,
+            cpu_picking: true,
+            object_id_picking: false,
+            stats_overlay: false,
+            gpu_timing: true,
+            indirect_draws: true,
+            gpu_culling: false,
+            cpu_culling: true,
+            sort_draws: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Vsync {
+    Auto,
+    AutoOff,
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+impl Vsync {
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        match self {
+            Vsync::Auto => wgpu::PresentMode::AutoVsync,
+            Vsync::AutoOff => wgpu::PresentMode::AutoNoVsync,
+            Vsync::Fifo => wgpu::PresentMode::Fifo,
+            Vsync::Mailbox => wgpu::PresentMode::Mailbox,
+            Vsync::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Primary,
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+    All,
+}
+
+impl Backend {
+    pub fn backends(&self) -> wgpu::Backends {
+        match self {
+            Backend::Primary => wgpu::Backends::PRIMARY,
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::All => wgpu::Backends::all(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Loads the config file, then applies any command line overrides.
+    /// The file path can be changed with `--config <path>`, and a missing
+    /// file falls back to the defaults.
+    pub fn load() -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        let path = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .and_then(|index| args.get(index + 1))
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_CONFIG_PATH);
+
+        let mut config = Self::from_file(path);
+        config.apply_args(&args);
+        config.validate();
+        config
+    }
+
+    /// Clamps values the app can't run with, warning about each one.
+    pub fn validate(&mut self) {
+        if !self.tick_rate.is_finite() || self.tick_rate < MIN_TICK_RATE {
+            println!(
+                "Invalid tick rate {}, using {MIN_TICK_RATE} instead.",
+                self.tick_rate
+            );
+            self.tick_rate = MIN_TICK_RATE;
+        }
+
+        if self.max_ticks_per_frame == 0 {
+            println!("Invalid max ticks per frame 0, using 1 instead.");
+            self.max_ticks_per_frame = 1;
+        }
+
+        if self.frames_in_flight == 0 {
+            println!("Invalid frames in flight 0, using 1 instead.");
+            self.frames_in_flight = 1;
+        }
+    }
+
+    pub fn from_file(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(contents) => match toml::from_str(&contents) {
+                Ok(config) => config,
+                Err(e) => {
+                    println!("Invalid config file {path}, using defaults: {e}");
+                    Self::default()
+                }
+            },
+            Err(_) => {
+                println!("No config file found at {path}, using defaults.");
+                Self::default()
+            }
+        }
+    }
+
+    /// Applies `--key value` overrides. Keys match the names in the config file,
+    /// with dashes or underscores, ie `--tick-rate 30 --vsync mailbox --asset-root assets`.
+    pub fn apply_args(&mut self, args: &[String]) {
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let Some(key) = arg.strip_prefix("--") else {
+                println!("Ignoring unknown argument: {arg}");
+                continue;
+            };
+
+            let key = key.replace('-', "_");
+
+            let Some(value) = args.next() else {
+                println!("Missing value for argument: {arg}");
+                break;
+            };
+
+            match key.as_str() {
+                "config" => {}
+                "asset_root" => self.asset_root = PathBuf::from(value),
+                "default_matcap" => self.default_matcap.clone_from(value),
+                "record" => self.record = Some(PathBuf::from(value)),
+                "playback" => self.playback = Some(PathBuf::from(value)),
+                _ => {
+                    // Everything else is parsed the same way as the config file
+                    let toml = format!("{key} = {}", toml_value(value));
+                    match toml::from_str::<toml::Table>(&toml) {
+                        Ok(table) => self.apply_table(table),
+                        Err(e) => println!("Invalid value for {arg}: {e}"),
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_table(&mut self, table: toml::Table) {
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(
+                    if let Some(field) = table.get(stringify!($field)) {
+                        match field.clone().try_into() {
+                            Ok(parsed) => self.$field = parsed,
+                            Err(e) => println!("Invalid value for {}: {e}", stringify!($field)),
+                        }
+                        return;
+                    }
+                )*
+            };
+        }
+
+        apply!(
+            resolution,
+            tick_rate,
+            max_ticks_per_frame,
+            window_mode,
+            vsync,
+            frame_latency,
+            frames_in_flight,
+            backend,
+            force_fallback_adapter,
+            headless,
+            frames,
+            cpu_picking,
+            object_id_picking,
+            stats_overlay,
+            gpu_timing,
+            indirect_draws,
+            gpu_culling,
+            cpu_culling,
+            sort_draws
+        );
+        println!("Unknown config override: {table}");
+    }
+
+    /// Device features to use if the adapter supports them.
+    pub fn optional_features(&self) -> wgpu::Features {
+        let mut features = wgpu::Features::empty();
+        if self.gpu_timing {
+            features |= gpu_timer::FEATURES;
+        }
+        if self.indirect_draws {
+            features |= indirect_draws::FEATURES;
+        }
+        if self.gpu_culling {
+            features |= gpu_culling::FEATURES;
+        }
+        features
+    }
+
+    pub fn display_settings(&self) -> DisplaySettings {
+        DisplaySettings {
+            window_mode: self.window_mode,
+            present_mode: self.vsync.present_mode(),
+            frame_latency: self.frame_latency,
+            cursor_grab: false,
+        }
+    }
+
+    /// Resolves a path relative to the asset root.
+    pub fn asset_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.asset_root.join(path)
+    }
+}
+
+// Command line values are bare words, so quote anything that isn't already a toml value
+fn toml_value(value: &str) -> String {
+    if value.parse::<f64>().is_ok() || value == "true" || value == "false" {
+        value.to_string()
+    } else {
+        format!("{value:?}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn apply_args_overrides_values() {
+        let mut config = AppConfig::default();
+        config.apply_args(&args(&[
+            "--tick-rate",
+            "30",
+            "--vsync",
+            "mailbox",
+            "--frames_in_flight",
+            "3",
+            "--asset-root",
+            "other assets",
+        ]));
+
+        assert_eq!(config.tick_rate, 30.0);
+        assert_eq!(config.vsync, Vsync::Mailbox);
+        assert_eq!(config.frames_in_flight, 3);
+        assert_eq!(config.asset_root, PathBuf::from("other assets"));
+    }
+
+    #[test]
+    fn apply_args_skips_unknown_keys() {
+        let mut config = AppConfig::default();
+        config.apply_args(&args(&["--not-a-setting", "1", "--tick-rate", "30"]));
+
+        assert_eq!(config.tick_rate, 30.0);
+    }
+
+    #[test]
+    fn apply_args_ignores_a_missing_value() {
+        let mut config = AppConfig::default();
+        config.apply_args(&args(&["--frames-in-flight", "3", "--tick-rate"]));
+
+        assert_eq!(config.frames_in_flight, 3);
+        assert_eq!(config.tick_rate, AppConfig::default().tick_rate);
+    }
+
+    #[test]
+    fn toml_value_quotes_bare_words() {
+        assert_eq!(toml_value("30"), "30");
+        assert_eq!(toml_value("0.5"), "0.5");
+        assert_eq!(toml_value("true"), "true");
+        assert_eq!(toml_value("mailbox"), "\"mailbox\"");
+    }
+
+    #[test]
+    fn validate_clamps_zero_counts() {
+        let mut config = AppConfig {
+            tick_rate: 0.0,
+            max_ticks_per_frame: 0,
+            frames_in_flight: 0,
+            ..AppConfig::default()
+        };
+        config.validate();
+
+        assert_eq!(config.tick_rate, MIN_TICK_RATE);
+        assert_eq!(config.max_ticks_per_frame, 1);
+        assert_eq!(config.frames_in_flight, 1);
+    }
+}
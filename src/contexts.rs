@@ -1,8 +1,24 @@
-use glam::Mat4;
+use std::collections::VecDeque;
+use std::path::PathBuf;
 
-use crate::{camera::Camera, lights::Light, pipeline::Pipeline};
+use glam::{Mat4, Quat, Vec3};
+
+use crate::{
+    camera::{Camera, Projection},
+    command_list::CommandList,
+    display::{DisplaySettings, WindowMode},
+    frame_stats::FrameStats,
+    gpu_timer::GpuTimings,
+    lights::Light,
+    picking::{MeshHandle, Ray, RayHit},
+    pipeline::Pipeline,
+};
 
 pub trait Init3dContext {
+    /// Resolves a path relative to the configured asset root.
+    fn asset_path(&self, path: &str) -> PathBuf;
+
+    /// Loads a texture, the path is relative to the asset root.
     fn load_texture(&mut self, path: &str, is_matcap: bool) -> usize;
 
     fn load_static_mesh(&mut self, data: &[f32], pipeline: Pipeline) -> usize;
@@ -13,10 +29,27 @@ pub trait Init3dContext {
         indices: &[u16],
         pipeline: Pipeline,
     ) -> usize;
+
+    /// Frees a mesh's space for meshes loaded later. The id isn't reused, and
+    /// draws of it are skipped.
+    #[allow(dead_code)]
+    fn unload_mesh(&mut self, mesh: MeshHandle);
+
+    /// Records where a mesh's data came from, ie its file, for resource listings.
+    fn set_mesh_source(&mut self, mesh: MeshHandle, source: &str);
 }
 
-pub trait Draw3dContext {
-    fn get_camera(&self) -> &Camera;
+/// Recording of draws, by the virtual gpu directly or into a `CommandList`.
+pub trait Record3dContext {
+    /// Starts a timed range of the frame, lasting until the next marker or the
+    /// end of the main pass. Ignored where timestamps inside passes aren't supported.
+    #[allow(dead_code)]
+    fn gpu_marker(&mut self, label: &'static str);
+
+    /// Sets the id written to the object id attachment by draws using the
+    /// matrices pushed after it. 0 means no object, and is reset every frame.
+    #[allow(dead_code)]
+    fn set_object_id(&mut self, id: u32);
 
     fn draw_tri_list(&mut self, data: &[f32], pipeline: Pipeline);
     fn push_light(&mut self, light: &Light);
@@ -27,3 +60,51 @@ pub trait Draw3dContext {
     fn set_texture(&mut self, tex_id: usize);
     fn set_matcap(&mut self, matcap_id: usize);
 }
+
+pub trait Draw3dContext: Record3dContext {
+    fn get_camera(&self) -> &Camera;
+
+    /// What the last rendered frame cost.
+    #[allow(dead_code)]
+    fn frame_stats(&self) -> &FrameStats;
+    /// Gpu timings of recent frames, oldest first. None if timestamp queries
+    /// aren't supported, or gpu timing is disabled.
+    #[allow(dead_code)]
+    fn gpu_timings(&self) -> Option<&VecDeque<GpuTimings>>;
+
+    /// Finds the closest static mesh hit by the ray, out of those drawn last
+    /// frame. Rays from the mouse come from `get_camera().screen_to_ray`.
+    fn raycast(&self, ray: &Ray) -> Option<RayHit>;
+
+    /// Returns the object id under a window pixel, if object id picking is
+    /// enabled. The pixel is read back asynchronously, so the result is from
+    /// the last finished pick, a frame or two old.
+    #[allow(dead_code)]
+    fn pick(&mut self, x: f32, y: f32) -> Option<u32>;
+
+    // Set the camera before pushing lights, they're moved into view space when pushed
+    #[allow(dead_code)]
+    fn set_camera_transform(&mut self, transform: Mat4);
+    fn set_camera(&mut self, position: Vec3, orientation: Quat);
+    fn look_at(&mut self, eye: Vec3, target: Vec3, up: Vec3);
+    fn set_fov(&mut self, fovy: f32);
+    #[allow(dead_code)]
+    fn set_near(&mut self, z_near: f32);
+    #[allow(dead_code)]
+    fn set_projection(&mut self, projection: Projection);
+
+    /// Adds a list's draws to the frame, after everything recorded so far.
+    /// Lights in the list are moved into view space with the current camera.
+    fn append_command_list(&mut self, list: &CommandList);
+
+    // Display changes are applied by the app after the frame is drawn
+    #[allow(dead_code)]
+    fn get_display_settings(&self) -> DisplaySettings;
+    #[allow(dead_code)]
+    fn set_window_mode(&mut self, mode: WindowMode);
+    #[allow(dead_code)]
+    fn set_present_mode(&mut self, mode: wgpu::PresentMode);
+    #[allow(dead_code)]
+    fn set_frame_latency(&mut self, latency: u32);
+    fn set_cursor_grab(&mut self, grab: bool);
+}
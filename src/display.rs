@@ -0,0 +1,107 @@
+use serde::Deserialize;
+use wgpu::PresentMode;
+use winit::window::{CursorGrabMode, Fullscreen, Window};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+    Exclusive,
+}
+
+impl WindowMode {
+    pub fn next(&self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+
+    /// Returns the winit fullscreen setting for this mode. Exclusive mode
+    /// picks the largest, fastest video mode of the current monitor, and
+    /// falls back to borderless if none are available.
+    pub fn fullscreen(&self, window: &Window) -> Option<Fullscreen> {
+        match self {
+            WindowMode::Windowed => None,
+            WindowMode::Borderless => Some(Fullscreen::Borderless(None)),
+            WindowMode::Exclusive => {
+                let video_mode = window.current_monitor().and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                });
+
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => {
+                        println!("No exclusive video modes found, using borderless.");
+                        Some(Fullscreen::Borderless(None))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DisplaySettings {
+    pub window_mode: WindowMode,
+    pub present_mode: PresentMode,
+    pub frame_latency: u32,
+    /// Hides the cursor and keeps it in the window, ie for mouse look.
+    pub cursor_grab: bool,
+}
+
+/// Cycles through the vsync modes, skipping any not supported by the surface.
+pub fn next_present_mode(current: PresentMode, supported: &[PresentMode]) -> PresentMode {
+    const MODES: [PresentMode; 3] = [
+        PresentMode::Fifo,
+        PresentMode::Mailbox,
+        PresentMode::Immediate,
+    ];
+
+    let start = MODES.iter().position(|mode| *mode == current).unwrap_or(0);
+
+    (1..=MODES.len())
+        .map(|offset| MODES[(start + offset) % MODES.len()])
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Cycles the frames the surface may queue up through 1, 2 and 3.
+pub fn next_frame_latency(current: u32) -> u32 {
+    const MAX_FRAME_LATENCY: u32 = 3;
+    current % MAX_FRAME_LATENCY + 1
+}
+
+/// Returns the mode if the surface supports it, otherwise falls back to Fifo
+/// which is always available. The Auto modes are resolved by wgpu itself.
+pub fn supported_present_mode(mode: PresentMode, supported: &[PresentMode]) -> PresentMode {
+    match mode {
+        PresentMode::AutoVsync | PresentMode::AutoNoVsync => mode,
+        mode if supported.contains(&mode) => mode,
+        mode => {
+            println!("Present mode {mode:?} not supported, using Fifo.");
+            PresentMode::Fifo
+        }
+    }
+}
+
+/// Locks the cursor in place where supported, otherwise confines it to the window.
+pub fn grab_cursor(window: &Window, grab: bool) {
+    let result = if grab {
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+
+    if let Err(e) = result {
+        println!("Failed to grab cursor: {e}");
+    }
+    window.set_cursor_visible(!grab);
+}
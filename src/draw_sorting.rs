@@ -0,0 +1,401 @@
+use glam::Mat4;
+
+use crate::{
+    frame_stats::StateSwitches,
+    picking::MeshHandle,
+    pipeline::Pipeline,
+    preloaded_renderer::PreloadedRenderer,
+    virtual_render_pass::{Command, VirtualRenderPass},
+};
+
+// Pipeline, then the texture and matcap it reads, then mesh. Unloaded meshes go last, they're skipped anyway
+type SortKey = (usize, Option<usize>, Option<usize>, bool, usize);
+
+// A static mesh draw taken out of the recorded order, with the state it was recorded with
+struct SortedDraw {
+    command: Command,
+    key: SortKey,
+    state: State,
+    uses_texture: bool,
+    uses_matcap: bool,
+}
+
+// What's bound at a point in the frame. None until the frame sets it
+#[derive(Clone, Copy, Default)]
+struct State {
+    texture: Option<usize>,
+    matcap: Option<usize>,
+    instance: Option<usize>,
+}
+
+/// Reorders the static mesh draws between other draws by pipeline, then
+/// texture and matcap, then mesh, so fewer state changes are needed. Every
+/// 3d static mesh is opaque, so only draws at equal depths can look different.
+/// 2d static meshes stay in place, like other draws.
+///
+/// Each draw keeps its own matrix and textures, set again only where they
+/// change. Matrices are rewritten in the new order. Returns the switches the
+/// frame needed in its recorded order.
+pub fn sort_draws(pass: &mut VirtualRenderPass, meshes: &PreloadedRenderer) -> StateSwitches {
+    let unsorted = count_switches(&pass.commands, meshes);
+    // The frame debugger highlights the last draw, which has to stay last
+    if pass
+        .commands
+        .iter()
+        .any(|command| matches!(command, Command::Highlight))
+    {
+        return unsorted;
+    }
+
+    let mut sorter = Sorter {
+        pass,
+        commands: Vec::new(),
+        matrices: Vec::new(),
+        object_ids: Vec::new(),
+        run: Vec::new(),
+        recorded: State::default(),
+        emitted: State::default(),
+        pending_pipeline: None,
+    };
+    let mut instances = 0;
+    for command in std::mem::take(&mut sorter.pass.commands) {
+        match command {
+            Command::SetPipeline(pipeline) => sorter.pending_pipeline = Some(pipeline),
+            Command::SetTexture(texture) => sorter.recorded.texture = Some(texture),
+            Command::SetMatcap(matcap) => sorter.recorded.matcap = Some(matcap),
+            Command::SetModelMatrix => {
+                sorter.recorded.instance = Some(instances);
+                instances += 1;
+            }
+            Command::DrawStaticMesh(index) | Command::DrawStaticMeshIndexed(index) => {
+                let indexed = matches!(command, Command::DrawStaticMeshIndexed(_));
+                let mesh = if indexed {
+                    MeshHandle::Indexed(index)
+                } else {
+                    MeshHandle::Static(index)
+                };
+                let pipeline = meshes.pipeline(mesh);
+                // 2d meshes are layered in the order they're drawn, like sprites
+                if pipeline == Some(Pipeline::Quad2d) {
+                    sorter.push_in_order(command);
+                } else {
+                    sorter.push_draw(command, pipeline, indexed, index);
+                }
+            }
+            // Other draws and markers stay where they were, static meshes are only sorted between them
+            _ => {
+                sorter.push_in_order(command);
+                // Sprites bind their own texture, which later draws are left with
+                if let Command::DrawSprite(texture) = command {
+                    sorter.recorded.texture = Some(texture);
+                    sorter.emitted.texture = Some(texture);
+                }
+            }
+        }
+    }
+    sorter.flush();
+
+    let Sorter {
+        pass,
+        commands,
+        matrices,
+        object_ids,
+        ..
+    } = sorter;
+    pass.commands = commands;
+    pass.matrices = matrices;
+    pass.object_ids = object_ids;
+    unsorted
+}
+
+/// Pipeline and bind group switches needed to draw the commands in order,
+/// only counting real changes like the render pass does.
+pub fn count_switches(commands: &[Command], meshes: &PreloadedRenderer) -> StateSwitches {
+    let mut switches = StateSwitches::default();
+    let mut pipeline = None;
+    let mut texture = None;
+    let mut matcap = None;
+
+    for command in commands {
+        let (next_pipeline, next_texture) = match *command {
+            Command::SetPipeline(pipeline) => (Some(pipeline), None),
+            Command::DrawStaticMesh(index) => (meshes.pipeline(MeshHandle::Static(index)), None),
+            Command::DrawStaticMeshIndexed(index) => {
+                (meshes.pipeline(MeshHandle::Indexed(index)), None)
+            }
+            Command::DrawSprite(sprite) => (Some(Pipeline::Quad2d), Some(sprite)),
+            Command::SetTexture(texture) => (None, Some(texture)),
+            Command::SetMatcap(next) => {
+                if matcap != Some(next) {
+                    matcap = Some(next);
+                    switches.bind_groups += 1;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        if next_pipeline.is_some() && next_pipeline != pipeline {
+            pipeline = next_pipeline;
+            switches.pipelines += 1;
+        }
+        if next_texture.is_some() && next_texture != texture {
+            texture = next_texture;
+            switches.bind_groups += 1;
+        }
+    }
+    switches
+}
+
+struct Sorter<'a> {
+    pass: &'a mut VirtualRenderPass,
+    commands: Vec<Command>,
+    matrices: Vec<Mat4>,
+    object_ids: Vec<u32>,
+
+    // Static mesh draws since the last other draw
+    run: Vec<SortedDraw>,
+    // The state as recorded so far, and as set by the sorted commands
+    recorded: State,
+    emitted: State,
+    // Set since the last command pushed in order. Only immediate draws use it, static meshes use their own
+    pending_pipeline: Option<Pipeline>,
+}
+
+impl Sorter<'_> {
+    fn push_draw(
+        &mut self,
+        command: Command,
+        pipeline: Option<Pipeline>,
+        indexed: bool,
+        index: usize,
+    ) {
+        let uses_texture = pipeline.is_some_and(|pipeline| pipeline.has_uv());
+        let uses_matcap = pipeline.is_some_and(|pipeline| pipeline.has_matcap());
+        self.run.push(SortedDraw {
+            command,
+            key: (
+                pipeline.map_or(usize::MAX, |pipeline| pipeline.get_shader()),
+                self.recorded.texture.filter(|_| uses_texture),
+                self.recorded.matcap.filter(|_| uses_matcap),
+                indexed,
+                index,
+            ),
+            state: self.recorded,
+            uses_texture,
+            uses_matcap,
+        });
+    }
+
+    // Draws the run in sorted order
+    fn flush(&mut self) {
+        let mut run = std::mem::take(&mut self.run);
+        run.sort_by_key(|draw| draw.key);
+        for draw in run {
+            // What the pipeline doesn't read is left as is, once anything is bound
+            let mut state = draw.state;
+            if !draw.uses_texture && self.emitted.texture.is_some() {
+                state.texture = None;
+            }
+            if !draw.uses_matcap && self.emitted.matcap.is_some() {
+                state.matcap = None;
+            }
+            self.emit(state);
+            self.commands.push(draw.command);
+        }
+    }
+
+    // Ends the run, and pushes a command with the state as it was recorded
+    fn push_in_order(&mut self, command: Command) {
+        self.flush();
+        self.emit(self.recorded);
+        if let Some(pipeline) = self.pending_pipeline.take() {
+            self.commands.push(Command::SetPipeline(pipeline));
+        }
+        self.commands.push(command);
+    }
+
+    // Unset state is left alone, draws recorded before it was set didn't depend on it
+    fn emit(&mut self, state: State) {
+        if let Some(texture) = state.texture.filter(|t| self.emitted.texture != Some(*t)) {
+            self.commands.push(Command::SetTexture(texture));
+            self.emitted.texture = Some(texture);
+        }
+        if let Some(matcap) = state.matcap.filter(|m| self.emitted.matcap != Some(*m)) {
+            self.commands.push(Command::SetMatcap(matcap));
+            self.emitted.matcap = Some(matcap);
+        }
+        if let Some(instance) = state.instance {
+            if self.emitted.instance != Some(instance) {
+                self.commands.push(Command::SetModelMatrix);
+                self.matrices.push(self.pass.matrices[instance]);
+                self.object_ids.push(self.pass.object_ids[instance]);
+                self.emitted.instance = Some(instance);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::mesh::{Bounds, Mesh};
+
+    // Static mesh ids are their index in this list
+    const PIPELINES: [Pipeline; 4] = [
+        Pipeline::Matcap,
+        Pipeline::Color,
+        Pipeline::Quad2d,
+        Pipeline::Uv,
+    ];
+
+    fn meshes() -> PreloadedRenderer {
+        let mut meshes = PreloadedRenderer::new(false);
+        for pipeline in PIPELINES {
+            meshes.meshes.push(Some(Mesh {
+                arena: 0,
+                first_vertex: 0,
+                vertex_count: 3,
+                pipeline,
+                data: Vec::new(),
+                positions: None,
+                bounds: Bounds::default(),
+                source: None,
+            }));
+        }
+        meshes
+    }
+
+    // Records a draw of a static mesh with its own matrix and object id
+    fn draw(pass: &mut VirtualRenderPass, mesh: usize) {
+        let id = pass.matrices.len() as u32 + 1;
+        pass.commands.push(Command::SetModelMatrix);
+        pass.matrices
+            .push(Mat4::from_translation(Vec3::splat(id as f32)));
+        pass.object_ids.push(id);
+        pass.commands.push(Command::DrawStaticMesh(mesh));
+    }
+
+    // A static mesh draw with the matrix, object id, and the texture and matcap its pipeline reads
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Replayed {
+        command: Command,
+        matrix: Mat4,
+        object_id: u32,
+        texture: Option<usize>,
+        matcap: Option<usize>,
+    }
+
+    fn replay(pass: &VirtualRenderPass) -> Vec<Replayed> {
+        let mut draws = Vec::new();
+        let (mut instance, mut texture, mut matcap) = (None, None, None);
+        for command in pass.commands.iter() {
+            match *command {
+                Command::SetModelMatrix => instance = Some(instance.map_or(0, |i| i + 1)),
+                Command::SetTexture(next) | Command::DrawSprite(next) => texture = Some(next),
+                Command::SetMatcap(next) => matcap = Some(next),
+                Command::DrawStaticMesh(mesh) => {
+                    let instance = instance.unwrap();
+                    let pipeline = PIPELINES[mesh];
+                    draws.push(Replayed {
+                        command: *command,
+                        matrix: pass.matrices[instance],
+                        object_id: pass.object_ids[instance],
+                        texture: texture.filter(|_| pipeline.has_uv()),
+                        matcap: matcap.filter(|_| pipeline.has_matcap()),
+                    });
+                }
+                _ => {}
+            }
+        }
+        draws
+    }
+
+    #[test]
+    fn sorted_draws_keep_their_state() {
+        let meshes = meshes();
+        let mut pass = VirtualRenderPass::new();
+        for (texture, matcap) in [(4, 0), (5, 1), (4, 0)] {
+            pass.commands.push(Command::SetTexture(texture));
+            draw(&mut pass, 3);
+            pass.commands.push(Command::SetMatcap(matcap));
+            draw(&mut pass, 0);
+        }
+        draw(&mut pass, 1);
+
+        let recorded = replay(&pass);
+        let unsorted = sort_draws(&mut pass, &meshes);
+        let sorted = replay(&pass);
+
+        // A texture or matcap that wasn't set yet when a draw was recorded can be anything
+        let same = |a: Option<usize>, b: Option<usize>| a.is_none() || a == b;
+        assert_eq!(sorted.len(), recorded.len());
+        for draw in recorded.iter() {
+            assert!(
+                sorted.iter().any(|sorted| sorted.command == draw.command
+                    && sorted.matrix == draw.matrix
+                    && sorted.object_id == draw.object_id
+                    && same(draw.texture, sorted.texture)
+                    && same(draw.matcap, sorted.matcap)),
+                "{draw:?}"
+            );
+        }
+
+        // Uv and Matcap three times each then Color, and every texture and matcap change
+        assert_eq!(unsorted.pipelines, 7);
+        assert_eq!(unsorted.bind_groups, 6);
+        // Color binds the last texture and matcap, then Uv switches to texture 5, and Matcap to matcap 1
+        let switches = count_switches(&pass.commands, &meshes);
+        assert_eq!(switches.pipelines, 3);
+        assert_eq!(switches.bind_groups, 4);
+    }
+
+    #[test]
+    fn sorting_stops_at_2d_meshes() {
+        let meshes = meshes();
+        let mut pass = VirtualRenderPass::new();
+        draw(&mut pass, 3);
+        draw(&mut pass, 1);
+        draw(&mut pass, 2);
+        draw(&mut pass, 3);
+        draw(&mut pass, 1);
+
+        let recorded = replay(&pass);
+        sort_draws(&mut pass, &meshes);
+        let sorted = replay(&pass);
+
+        // Each side of the 2d mesh is sorted on its own, the 2d mesh stays in the middle
+        assert_eq!(sorted[..2], [recorded[1], recorded[0]]);
+        assert_eq!(sorted[2], recorded[2]);
+        assert_eq!(sorted[3..], [recorded[4], recorded[3]]);
+    }
+
+    #[test]
+    fn counts_only_real_switches() {
+        let meshes = meshes();
+        let commands = [
+            Command::SetTexture(0),
+            Command::DrawStaticMesh(3),
+            Command::SetTexture(0),
+            Command::DrawStaticMesh(3),
+            Command::SetTexture(1),
+            Command::DrawStaticMesh(1),
+            Command::SetMatcap(0),
+            Command::DrawStaticMesh(0),
+            Command::SetMatcap(0),
+            Command::DrawStaticMesh(0),
+            Command::DrawSprite(1),
+            Command::DrawSprite(2),
+            Command::SetPipeline(Pipeline::Quad2d),
+            Command::DrawStaticMesh(2),
+        ];
+
+        let switches = count_switches(&commands, &meshes);
+        // Uv, Color, Matcap, then Quad2d for the sprites and the 2d mesh
+        assert_eq!(switches.pipelines, 4);
+        // Textures 0 and 1, matcap 0, then the second sprite's texture
+        assert_eq!(switches.bind_groups, 4);
+    }
+}
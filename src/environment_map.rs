@@ -1,11 +1,17 @@
+use std::path::PathBuf;
+
 use glam::Vec4;
 use image::ImageReader;
 
+use crate::resources::TextureInfo;
+
 pub struct EnvironmentMap {
     pub uniforms_buffer: wgpu::Buffer,
-    pub uniforms: EnvironmentUniforms,
+    pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    // The six images, for resource listings
+    source: String,
 }
 
 pub struct EnvironmentUniforms {
@@ -32,16 +38,7 @@ impl EnvironmentUniforms {
 // Back
 
 impl EnvironmentMap {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
-        const IMAGES: [&str; 6] = [
-            "assets/skybox3/right.png",
-            "assets/skybox3/left.png",
-            "assets/skybox3/top.png",
-            "assets/skybox3/bottom.png",
-            "assets/skybox3/front.png",
-            "assets/skybox3/back.png",
-        ];
-
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, images: &[PathBuf; 6]) -> Self {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Environment Map Texture"),
             size: wgpu::Extent3d {
@@ -83,9 +80,7 @@ impl EnvironmentMap {
             mapped_at_creation: false,
         });
 
-        let uniforms = EnvironmentUniforms::new();
-
-        for (index, path) in IMAGES.iter().enumerate() {
+        for (index, path) in images.iter().enumerate() {
             let image = ImageReader::open(path).unwrap().decode().unwrap();
             let image = image.to_rgba8();
             let dimensions = image.dimensions();
@@ -118,9 +113,32 @@ impl EnvironmentMap {
 
         Self {
             uniforms_buffer,
-            uniforms,
+            texture,
             view,
             sampler,
+            source: images
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+
+    pub fn info(&self) -> TextureInfo {
+        let texture = &self.texture;
+        let block_size = texture.format().block_copy_size(None).unwrap_or(4);
+        TextureInfo {
+            id: None,
+            width: texture.width(),
+            height: texture.height(),
+            layers: texture.depth_or_array_layers(),
+            format: texture.format(),
+            is_matcap: false,
+            source: self.source.clone(),
+            bytes: texture.width() as u64
+                * texture.height() as u64
+                * texture.depth_or_array_layers() as u64
+                * block_size as u64,
         }
     }
 }
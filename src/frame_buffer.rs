@@ -13,19 +13,25 @@ pub struct FrameBuffer {
     width: u32,
     height: u32,
     pub scaling_buffer: wgpu::Buffer,
-    pub scaling: [f32; 2],
     pub scaling_bind_group: wgpu::BindGroup,
 }
 
+/// The frame buffer's size in the window as a fraction of it, scaled by
+/// whole pixels and letterboxed.
+pub fn letterbox_scaling(size: (u32, u32), surface_width: u32, surface_height: u32) -> [f32; 2] {
+    let (frame_width, frame_height) = size;
+    let int_width = surface_width / frame_width;
+    let int_height = surface_height / frame_height;
+    let scaling = int_width.min(int_height);
+
+    let width = (frame_width * scaling) as f32 / surface_width as f32;
+    let height = (frame_height * scaling) as f32 / surface_height as f32;
+    [width, height]
+}
+
 impl FrameBuffer {
-    pub fn adjust_scale(&mut self, surface_width: u32, surface_height: u32) {
-        let int_width = surface_width / self.width;
-        let int_height = surface_height / self.height;
-        let scaling = int_width.min(int_height);
-
-        let width = (self.width * scaling) as f32 / surface_width as f32;
-        let height = (self.height * scaling) as f32 / surface_height as f32;
-        self.scaling = [width, height];
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
     }
 
     pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
@@ -155,7 +161,6 @@ impl FrameBuffer {
             texture_bind_group,
             pipeline,
             scaling_buffer,
-            scaling: [1.0, 1.0],
             scaling_bind_group,
         }
     }
@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use crate::{
+    pipeline::Pipeline,
+    preloaded_renderer::PreloadedRenderer,
+    textures::Textures,
+    virtual_render_pass::{Command, VirtualRenderPass},
+};
+
+// Commands listed around the selected one
+const LISTED_COMMANDS: usize = 24;
+
+/// Freezes the last rendered frame, and replays it up to a selected command
+/// with the geometry of that command highlighted. Frames keep their staged
+/// vertex, instance and light data, so they can be replayed as often as needed.
+#[derive(Default)]
+pub struct FrameDebugger {
+    last_frame: Option<VirtualRenderPass>,
+    frozen: Option<VirtualRenderPass>,
+    selected: usize,
+}
+
+impl FrameDebugger {
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// Keeps a frame that was just rendered, unless the
+    /// frozen frame is being replayed.
+    pub fn capture(&mut self, pass: &mut VirtualRenderPass) {
+        if self.is_frozen() {
+            return;
+        }
+
+        let frame = self.last_frame.get_or_insert_with(VirtualRenderPass::new);
+        std::mem::swap(frame, pass);
+    }
+
+    /// Freezes the last frame with its last command selected, or unfreezes.
+    /// Returns false if there was no frame to freeze.
+    pub fn toggle(&mut self) -> bool {
+        if self.frozen.take().is_some() {
+            return true;
+        }
+        let Some(frame) = self.last_frame.take_if(|frame| !frame.commands.is_empty()) else {
+            return false;
+        };
+
+        self.selected = frame.commands.len() - 1;
+        self.frozen = Some(frame);
+        true
+    }
+
+    pub fn step(&mut self, forward: bool) {
+        let Some(frame) = &self.frozen else {
+            return;
+        };
+        self.selected = if forward {
+            (self.selected + 1).min(frame.commands.len() - 1)
+        } else {
+            self.selected.saturating_sub(1)
+        };
+    }
+
+    /// Moves the selection to the next or previous draw, if there is one.
+    pub fn step_draw(&mut self, forward: bool) {
+        let Some(frame) = &self.frozen else {
+            return;
+        };
+        let draw = if forward {
+            (self.selected + 1..frame.commands.len()).find(|&i| frame.commands[i].is_draw())
+        } else {
+            (0..self.selected)
+                .rev()
+                .find(|&i| frame.commands[i].is_draw())
+        };
+        if let Some(draw) = draw {
+            self.selected = draw;
+        }
+    }
+
+    /// Loads the frozen frame's commands up to the selected one into the
+    /// pass, followed by a highlight of the selected draw.
+    pub fn replay(&self, pass: &mut VirtualRenderPass) {
+        let Some(frame) = &self.frozen else {
+            return;
+        };
+
+        let commands = &frame.commands[..=self.selected];
+        let marker_count = commands
+            .iter()
+            .filter(|command| matches!(command, Command::GpuMarker(_)))
+            .count();
+
+        pass.commands.extend_from_slice(commands);
+        pass.commands.push(Command::Highlight);
+        // Matrices of the skipped commands are still staged, so anything drawn
+        // over the replay comes after them
+        let skipped_matrices = frame.commands[self.selected + 1..]
+            .iter()
+            .filter(|command| matches!(command, Command::SetModelMatrix));
+        pass.commands.extend(skipped_matrices);
+        pass.markers = frame.markers[..marker_count].to_vec();
+        pass.immediate_data.clone_from(&frame.immediate_data);
+        pass.matrices.clone_from(&frame.matrices);
+        pass.object_ids.clone_from(&frame.object_ids);
+        pass.lights.clone_from(&frame.lights);
+    }
+
+    /// A header, and the commands around the selected one with their arguments.
+    pub fn lines(&self, textures: &Textures, meshes: &PreloadedRenderer) -> Vec<String> {
+        let Some(frame) = &self.frozen else {
+            return Vec::new();
+        };
+
+        let mut lines = vec![format!(
+            "FRAME DEBUGGER {}/{}",
+            self.selected + 1,
+            frame.commands.len()
+        )];
+
+        let first = self
+            .selected
+            .saturating_sub(LISTED_COMMANDS / 2)
+            .min(frame.commands.len().saturating_sub(LISTED_COMMANDS));
+        let last = (first + LISTED_COMMANDS).min(frame.commands.len());
+        for index in first..last {
+            let cursor = if index == self.selected { ">" } else { " " };
+            lines.push(format!(
+                "{cursor}{index:>5} {}",
+                describe(&frame.commands[index], textures, meshes, &frame.markers)
+            ));
+        }
+        lines
+    }
+
+    /// Every command of the frozen frame, for printing.
+    pub fn listing(&self, textures: &Textures, meshes: &PreloadedRenderer) -> Vec<String> {
+        let Some(frame) = &self.frozen else {
+            return Vec::new();
+        };
+        frame
+            .commands
+            .iter()
+            .enumerate()
+            .map(|(index, command)| {
+                format!(
+                    "{index:>5} {}",
+                    describe(command, textures, meshes, &frame.markers)
+                )
+            })
+            .collect()
+    }
+}
+
+fn describe(
+    command: &Command,
+    textures: &Textures,
+    meshes: &PreloadedRenderer,
+    markers: &[&'static str],
+) -> String {
+    let texture = |id: usize| {
+        let source = &textures.textures[id].source;
+        let name = Path::new(source)
+            .file_name()
+            .map_or(source.clone(), |name| name.to_string_lossy().into_owned());
+        format!("{id} {name}")
+    };
+    let mesh = |pipeline: Pipeline, source: &Option<String>, count: u32| {
+        format!(
+            "{} {count} {}",
+            pipeline.name(),
+            source.as_deref().unwrap_or("")
+        )
+    };
+
+    match *command {
+        Command::SetPipeline(pipeline) => format!("SET PIPELINE {}", pipeline.name()),
+        Command::Draw(vertex_count) => format!("DRAW {vertex_count}"),
+        Command::SetTexture(id) => format!("SET TEXTURE {}", texture(id)),
+        Command::SetMatcap(id) => format!("SET MATCAP {}", texture(id)),
+        Command::SetModelMatrix => "SET MODEL MATRIX".to_string(),
+        Command::DrawStaticMesh(index) => match &meshes.meshes[index] {
+            Some(static_mesh) => format!(
+                "DRAW MESH {index} {}",
+                mesh(
+                    static_mesh.pipeline,
+                    &static_mesh.source,
+                    static_mesh.vertex_count
+                )
+            ),
+            None => format!("DRAW MESH {index} UNLOADED"),
+        },
+        Command::DrawStaticMeshIndexed(index) => match &meshes.indexed_meshes[index] {
+            Some(indexed) => format!(
+                "DRAW INDEXED {index} {}",
+                mesh(indexed.pipeline, &indexed.source, indexed.index_count)
+            ),
+            None => format!("DRAW INDEXED {index} UNLOADED"),
+        },
+        Command::DrawSprite(id) => format!("DRAW SPRITE {}", texture(id)),
+        Command::GpuMarker(marker) => format!("MARKER {}", markers[marker]),
+        Command::Highlight => "HIGHLIGHT".to_string(),
+    }
+}
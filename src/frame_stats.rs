@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use crate::virtual_render_pass::Command;
+
+/// Number of recorded commands of each kind.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CommandCounts {
+    pub set_pipeline: u32,
+    pub draw: u32,
+    pub set_texture: u32,
+    pub set_matcap: u32,
+    pub set_model_matrix: u32,
+    pub draw_static_mesh: u32,
+    pub draw_static_mesh_indexed: u32,
+    pub draw_sprite: u32,
+    pub gpu_marker: u32,
+    pub highlight: u32,
+}
+
+impl CommandCounts {
+    pub fn count(&mut self, command: &Command) {
+        let count = match command {
+            Command::SetPipeline(_) => &mut self.set_pipeline,
+            Command::Draw(_) => &mut self.draw,
+            Command::SetTexture(_) => &mut self.set_texture,
+            Command::SetMatcap(_) => &mut self.set_matcap,
+            Command::SetModelMatrix => &mut self.set_model_matrix,
+            Command::DrawStaticMesh(_) => &mut self.draw_static_mesh,
+            Command::DrawStaticMeshIndexed(_) => &mut self.draw_static_mesh_indexed,
+            Command::DrawSprite(_) => &mut self.draw_sprite,
+            Command::GpuMarker(_) => &mut self.gpu_marker,
+            Command::Highlight => &mut self.highlight,
+        };
+        *count += 1;
+    }
+
+    pub fn total(&self) -> u32 {
+        self.set_pipeline
+            + self.draw
+            + self.set_texture
+            + self.set_matcap
+            + self.set_model_matrix
+            + self.draw_static_mesh
+            + self.draw_static_mesh_indexed
+            + self.draw_sprite
+            + self.gpu_marker
+            + self.highlight
+    }
+}
+
+/// Pipeline and texture or matcap bind group changes in a frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StateSwitches {
+    pub pipelines: u32,
+    pub bind_groups: u32,
+}
+
+/// What the last rendered frame cost, collected by the virtual gpu.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    /// Cpu time the game spent recording the frame's draws.
+    pub record_time: Duration,
+    /// Cpu time spent encoding the recorded commands into wgpu passes.
+    pub encode_time: Duration,
+    /// Cpu time spent in the queue submit.
+    pub submit_time: Duration,
+
+    pub commands: CommandCounts,
+    pub draw_calls: u32,
+    /// Indirect draw calls, each drawing one or more static meshes.
+    pub indirect_calls: u32,
+    pub pipeline_switches: u32,
+    pub bind_group_switches: u32,
+    /// What the frame would have switched in recorded order, when draws are sorted.
+    pub unsorted_switches: Option<StateSwitches>,
+    pub triangles: u64,
+    /// Static mesh draws tested against the camera frustum when recorded, and those culled.
+    pub tested_meshes: u32,
+    pub culled_meshes: u32,
+
+    pub immediate_bytes: u64,
+    pub instance_bytes: u64,
+    pub light_bytes: u64,
+}
+
+impl FrameStats {
+    /// The stats as lines of text, as shown by the overlay.
+    pub fn lines(&self) -> Vec<String> {
+        let commands = &self.commands;
+        let mut switches = format!(
+            "SWITCHES PIPELINE {} BIND GROUP {}",
+            self.pipeline_switches, self.bind_group_switches
+        );
+        if let Some(unsorted) = self.unsorted_switches {
+            switches += &format!(" UNSORTED {} {}", unsorted.pipelines, unsorted.bind_groups);
+        }
+        vec![
+            format!(
+                "CPU RECORD {:.2} ENCODE {:.2} SUBMIT {:.2} MS",
+                self.record_time.as_secs_f64() * 1000.0,
+                self.encode_time.as_secs_f64() * 1000.0,
+                self.submit_time.as_secs_f64() * 1000.0,
+            ),
+            format!(
+                "DRAWS {} INDIRECT {} TRIS {}",
+                self.draw_calls, self.indirect_calls, self.triangles
+            ),
+            switches,
+            format!(
+                "CPU CULLED {} OF {} MESHES",
+                self.culled_meshes, self.tested_meshes
+            ),
+            format!(
+                "UPLOAD IMM {} INST {} LIGHT {} BYTES",
+                self.immediate_bytes, self.instance_bytes, self.light_bytes
+            ),
+            format!(
+                "COMMANDS {} PIPE {} DRAW {} TEX {} MATCAP {}",
+                commands.total(),
+                commands.set_pipeline,
+                commands.draw,
+                commands.set_texture,
+                commands.set_matcap,
+            ),
+            format!(
+                "MATRIX {} MESH {} INDEXED {} SPRITE {} MARKER {}",
+                commands.set_model_matrix,
+                commands.draw_static_mesh,
+                commands.draw_static_mesh_indexed,
+                commands.draw_sprite,
+                commands.gpu_marker,
+            ),
+        ]
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::{Arc, Mutex};
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::util::DrawIndexedIndirectArgs;
+
+use crate::{
+    camera::Frustum,
+    indirect_draws::{IndirectBatch, IndirectDrawList, IndirectDraws, MAX_INDIRECT_DRAWS},
+};
+
+/// Requested when gpu culling is enabled. Without it every draw of a batch is
+/// issued, and culled ones draw no instances.
+pub const FEATURES: wgpu::Features = wgpu::Features::MULTI_DRAW_INDIRECT_COUNT;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Visible and culled per batch, then the total culled
+const COUNTS_SIZE: u64 = (MAX_INDIRECT_DRAWS as u64 * 2 + 1) * size_of::<u32>() as u64;
+const CULLED_OFFSET: u64 = MAX_INDIRECT_DRAWS as u64 * 2 * size_of::<u32>() as u64;
+
+#[derive(Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+struct CullUniforms {
+    planes: [[f32; 4]; 6],
+    draw_count: u32,
+    instance_base: u32,
+    max_batches: u32,
+    _padding: u32,
+}
+
+#[derive(Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+struct CullDraw {
+    sphere: [f32; 4],
+    batch: u32,
+    _padding: [u32; 3],
+}
+
+/// Instances tested and culled in a frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullCounts {
+    pub tested: u32,
+    pub culled: u32,
+}
+
+/// Culls indirect draws against the camera frustum in a compute pass before
+/// the main pass. Visible draws are compacted to the front of their batch, with
+/// their matrices and object ids copied after the recorded instances, so each
+/// batch only draws what's visible. Counts are read back like object ids, a
+/// frame or two late.
+pub struct GpuCulling {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    uniforms_buffer: wgpu::Buffer,
+    draws_buffer: wgpu::Buffer,
+    batches_buffer: wgpu::Buffer,
+    args_buffer: wgpu::Buffer,
+    counts_buffer: wgpu::Buffer,
+    instance_base: u32,
+    draw_count: bool,
+
+    readback_buffer: wgpu::Buffer,
+    // Draws tested in the frame being read back
+    copied: Option<u32>,
+    in_flight: Option<u32>,
+    mapped: Arc<Mutex<Option<bool>>>,
+    last_counts: Option<CullCounts>,
+}
+
+impl GpuCulling {
+    /// `instance_base` is where compacted instances start in the instance and
+    /// object id buffers, which need room for `MAX_INDIRECT_DRAWS` after it.
+    pub fn new(
+        device: &wgpu::Device,
+        indirect_draws: &IndirectDraws,
+        instance_buffer: &wgpu::Buffer,
+        object_id_buffer: &wgpu::Buffer,
+        instance_base: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Gpu Culling Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_culling.wgsl").into()),
+        });
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Gpu Culling Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, true),
+                storage(3, true),
+                storage(4, false),
+                storage(5, false),
+                storage(6, false),
+                storage(7, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gpu Culling Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Gpu Culling Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_cull"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let create_buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let uniforms_buffer = create_buffer(
+            "Gpu Culling Uniforms Buffer",
+            size_of::<CullUniforms>() as u64,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        );
+        let draws_buffer = create_buffer(
+            "Gpu Culling Draws Buffer",
+            (MAX_INDIRECT_DRAWS * size_of::<CullDraw>()) as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        let batches_buffer = create_buffer(
+            "Gpu Culling Batches Buffer",
+            (MAX_INDIRECT_DRAWS * size_of::<[u32; 2]>()) as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        let args_buffer = create_buffer(
+            "Culled Indirect Draw Buffer",
+            indirect_draws.buffer.size(),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+        );
+        let counts_buffer = create_buffer(
+            "Gpu Culling Counts Buffer",
+            COUNTS_SIZE,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+        );
+        let readback_buffer = create_buffer(
+            "Gpu Culling Readback Buffer",
+            size_of::<u32>() as u64,
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Gpu Culling Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                uniforms_buffer.as_entire_binding(),
+                indirect_draws.buffer.as_entire_binding(),
+                draws_buffer.as_entire_binding(),
+                batches_buffer.as_entire_binding(),
+                instance_buffer.as_entire_binding(),
+                object_id_buffer.as_entire_binding(),
+                args_buffer.as_entire_binding(),
+                counts_buffer.as_entire_binding(),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect::<Vec<_>>(),
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            uniforms_buffer,
+            draws_buffer,
+            batches_buffer,
+            args_buffer,
+            counts_buffer,
+            instance_base,
+            draw_count: device
+                .features()
+                .contains(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT),
+            readback_buffer,
+            copied: None,
+            in_flight: None,
+            mapped: Arc::new(Mutex::new(None)),
+            last_counts: None,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.uniforms_buffer.size()
+            + self.draws_buffer.size()
+            + self.batches_buffer.size()
+            + self.args_buffer.size()
+            + self.counts_buffer.size()
+            + self.readback_buffer.size()
+    }
+
+    pub fn last_counts(&self) -> Option<CullCounts> {
+        self.last_counts
+    }
+
+    /// Picks up a finished read of the counts, called before the frame is encoded.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let Some(tested) = self.in_flight else {
+            return;
+        };
+
+        device.poll(wgpu::Maintain::Poll);
+        let Some(success) = self.mapped.lock().unwrap().take() else {
+            return;
+        };
+        self.in_flight = None;
+        if !success {
+            return;
+        }
+
+        let culled = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+        };
+        self.readback_buffer.unmap();
+        self.last_counts = Some(CullCounts { tested, culled });
+    }
+
+    /// Uploads the frame's draws and encodes the culling pass. The indirect
+    /// args and instances it reads must already be written.
+    pub fn cull(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        draws: &IndirectDrawList,
+        frustum: &Frustum,
+    ) {
+        let draw_count = draws.args.len() as u32;
+        if draw_count == 0 {
+            return;
+        }
+
+        let uniforms = CullUniforms {
+            planes: frustum.planes.map(|plane| plane.to_array()),
+            draw_count,
+            instance_base: self.instance_base,
+            max_batches: MAX_INDIRECT_DRAWS as u32,
+            _padding: 0,
+        };
+        let cull_draws: Vec<CullDraw> = draws
+            .spheres
+            .iter()
+            .zip(draws.draw_batches.iter())
+            .map(|(sphere, batch)| CullDraw {
+                sphere: sphere.to_array(),
+                batch: *batch,
+                _padding: [0; 3],
+            })
+            .collect();
+        let batches: Vec<[u32; 2]> = draws
+            .batches
+            .iter()
+            .map(|batch| [batch.first, batch.count])
+            .collect();
+        queue.write_buffer(&self.uniforms_buffer, 0, bytemuck::bytes_of(&uniforms));
+        queue.write_buffer(&self.draws_buffer, 0, cast_slice(&cull_draws));
+        queue.write_buffer(&self.batches_buffer, 0, cast_slice(&batches));
+
+        encoder.clear_buffer(&self.counts_buffer, 0, None);
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Gpu Culling Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.dispatch_workgroups(draw_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+
+        // Only one read is in flight at a time
+        if self.in_flight.is_none() {
+            encoder.copy_buffer_to_buffer(
+                &self.counts_buffer,
+                CULLED_OFFSET,
+                &self.readback_buffer,
+                0,
+                size_of::<u32>() as u64,
+            );
+            // Draws that are never culled aren't counted as tested
+            let tested = draws
+                .spheres
+                .iter()
+                .filter(|sphere| sphere.w >= 0.0)
+                .count();
+            self.copied = Some(tested as u32);
+        }
+    }
+
+    /// Starts mapping the copied counts, once the frame has been submitted.
+    pub fn map_copied(&mut self) {
+        let Some(tested) = self.copied.take() else {
+            return;
+        };
+        self.in_flight = Some(tested);
+
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if let Err(e) = &result {
+                    println!("Failed to read back culling counts: {e}");
+                }
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+    }
+
+    /// Draws the visible draws of a batch, returning the number of calls it took.
+    pub fn draw(
+        &self,
+        rp: &mut wgpu::RenderPass,
+        indirect_draws: &IndirectDraws,
+        index: usize,
+        batch: IndirectBatch,
+    ) -> u32 {
+        if !self.draw_count {
+            return indirect_draws.draw(rp, &self.args_buffer, batch.first, batch.count);
+        }
+
+        let stride = size_of::<DrawIndexedIndirectArgs>() as u64;
+        rp.multi_draw_indexed_indirect_count(
+            &self.args_buffer,
+            batch.first as u64 * stride,
+            &self.counts_buffer,
+            (index * size_of::<u32>()) as u64,
+            batch.count,
+        );
+        1
+    }
+}
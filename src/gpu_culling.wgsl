@@ -0,0 +1,101 @@
+// Tests each indirect draw's bounding sphere against the frustum, and compacts
+// the visible ones to the front of their batch. Culled draws fill the back of
+// the batch with no instances, so batches drawn without a count still work.
+// Compacting doesn't keep the order within a batch, so batches of draws that
+// are never culled, ie 2d meshes, are passed through untouched.
+
+const WORKGROUP_SIZE = 64u;
+
+struct Cull {
+    planes: array<vec4<f32>, 6>,
+    draw_count: u32,
+    // Where compacted instances go in the instance buffer
+    instance_base: u32,
+    // Counts are laid out as visible per batch, culled per batch, then the totals
+    max_batches: u32,
+}
+
+// Same layout as wgpu's DrawIndexedIndirectArgs
+struct DrawArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+struct Draw {
+    // Center and radius in the mesh's own space, negative radii are never culled
+    sphere: vec4<f32>,
+    batch: u32,
+}
+
+struct Batch {
+    first: u32,
+    count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> input_args: array<DrawArgs>;
+@group(0) @binding(2)
+var<storage, read> draws: array<Draw>;
+@group(0) @binding(3)
+var<storage, read> batches: array<Batch>;
+@group(0) @binding(4)
+var<storage, read_write> instances: array<mat4x4<f32>>;
+@group(0) @binding(5)
+var<storage, read_write> object_ids: array<u32>;
+@group(0) @binding(6)
+var<storage, read_write> output_args: array<DrawArgs>;
+@group(0) @binding(7)
+var<storage, read_write> counts: array<atomic<u32>>;
+
+fn is_visible(sphere: vec4<f32>, model: mat4x4<f32>) -> bool {
+    let center = (model * vec4<f32>(sphere.xyz, 1.0)).xyz;
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = sphere.w * scale;
+    for (var i = 0; i < 6; i++) {
+        if dot(cull.planes[i], vec4<f32>(center, 1.0)) < -radius {
+            return false;
+        }
+    }
+    return true;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= cull.draw_count {
+        return;
+    }
+
+    let draw = draws[index];
+    let batch = batches[draw.batch];
+    var args = input_args[index];
+
+    // Batches share a pipeline, so these are never mixed with culled draws
+    if draw.sphere.w < 0.0 {
+        output_args[index] = args;
+        atomicAdd(&counts[draw.batch], 1u);
+        return;
+    }
+
+    let instance = args.first_instance;
+    let model = instances[instance];
+
+    if is_visible(draw.sphere, model) {
+        let slot = batch.first + atomicAdd(&counts[draw.batch], 1u);
+        let compacted = cull.instance_base + slot;
+        instances[compacted] = model;
+        object_ids[compacted] = object_ids[instance];
+        args.first_instance = compacted;
+        output_args[slot] = args;
+    } else {
+        let culled = atomicAdd(&counts[cull.max_batches + draw.batch], 1u);
+        args.instance_count = 0u;
+        output_args[batch.first + batch.count - 1u - culled] = args;
+        atomicAdd(&counts[cull.max_batches * 2u], 1u);
+    }
+}
@@ -0,0 +1,272 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Requested when gpu timing is enabled, used if the adapter supports them.
+pub const FEATURES: wgpu::Features =
+    wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES);
+
+// Queries 0 and 1 are the main pass, 2 and 3 the frame buffer pass, then one per marker
+const MAIN_PASS_QUERY: u32 = 0;
+const FRAME_BUFFER_PASS_QUERY: u32 = 2;
+const FIRST_MARKER_QUERY: u32 = 4;
+pub const MAX_MARKERS: usize = 60;
+const MAX_QUERIES: u32 = FIRST_MARKER_QUERY + MAX_MARKERS as u32;
+
+// Frames that can wait on a readback at once, frames beyond this go untimed
+const READBACK_COUNT: usize = 4;
+/// Frames of timings kept by the virtual gpu.
+pub const HISTORY_LENGTH: usize = 120;
+
+/// Gpu time spent on a frame.
+#[derive(Clone, Debug, Default)]
+pub struct GpuTimings {
+    pub main_pass: Duration,
+    pub frame_buffer_pass: Duration,
+    /// Time from each marker to the next one, or the end of the main pass.
+    pub ranges: Vec<(&'static str, Duration)>,
+}
+
+struct Readback {
+    buffer: wgpu::Buffer,
+    // Markers of the frame waiting on this buffer, None when it's free
+    markers: Option<Vec<&'static str>>,
+    // Set by the map callback, to whether the read succeeded
+    mapped: Arc<Mutex<Option<bool>>>,
+}
+
+/// Times the passes with timestamp queries, where the adapter supports them.
+/// Results are read back asynchronously, a few frames behind the one being
+/// rendered.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readbacks: Vec<Readback>,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    inside_passes: bool,
+
+    // The readback used by the frame being rendered, if one was free
+    active: Option<usize>,
+    resolved: Option<usize>,
+}
+
+impl GpuTimer {
+    /// Returns None if the device wasn't created with timestamp queries.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Timestamp Query Set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_QUERIES,
+        });
+
+        let size = MAX_QUERIES as u64 * size_of::<u64>() as u64;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readbacks = (0..READBACK_COUNT)
+            .map(|_| Readback {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Timestamp Readback Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                markers: None,
+                mapped: Arc::new(Mutex::new(None)),
+            })
+            .collect();
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readbacks,
+            period: queue.get_timestamp_period(),
+            inside_passes: device
+                .features()
+                .contains(wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES),
+            active: None,
+            resolved: None,
+        })
+    }
+
+    /// Bytes taken by the query set, and its resolve and readback buffers.
+    pub fn size(&self) -> u64 {
+        MAX_QUERIES as u64 * size_of::<u64>() as u64
+            + self.resolve_buffer.size()
+            + self
+                .readbacks
+                .iter()
+                .map(|readback| readback.buffer.size())
+                .sum::<u64>()
+    }
+
+    /// Markers need timestamps inside passes, they're ignored otherwise.
+    pub fn supports_markers(&self) -> bool {
+        self.inside_passes
+    }
+
+    /// Returns the timings of finished readbacks, and picks a free one for
+    /// the next frame.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) -> Vec<GpuTimings> {
+        device.poll(wgpu::Maintain::Poll);
+        let mut finished = Vec::new();
+
+        for readback in self.readbacks.iter_mut() {
+            let Some(success) = readback.mapped.lock().unwrap().take() else {
+                continue;
+            };
+            let markers = readback.markers.take().unwrap();
+            if !success {
+                continue;
+            }
+
+            let timestamps: Vec<u64> = {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                data.chunks_exact(size_of::<u64>())
+                    .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect()
+            };
+            readback.buffer.unmap();
+
+            finished.push(timings(&timestamps, &markers, self.period));
+        }
+
+        self.active = self
+            .readbacks
+            .iter()
+            .position(|readback| readback.markers.is_none());
+        finished
+    }
+
+    pub fn main_pass_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.pass_writes(MAIN_PASS_QUERY)
+    }
+
+    pub fn frame_buffer_pass_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.pass_writes(FRAME_BUFFER_PASS_QUERY)
+    }
+
+    fn pass_writes(&self, query: u32) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.active?;
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(query),
+            end_of_pass_write_index: Some(query + 1),
+        })
+    }
+
+    /// Writes the timestamp starting a marker's range, inside the main pass.
+    /// Markers past MAX_MARKERS have no query and are dropped.
+    pub fn write_marker(&self, render_pass: &mut wgpu::RenderPass, marker: usize) {
+        debug_assert!(marker < MAX_MARKERS, "marker {marker} past the limit");
+        let Some(query) = marker_query(marker) else {
+            return;
+        };
+        if self.active.is_some() && self.inside_passes {
+            render_pass.write_timestamp(&self.query_set, query);
+        }
+    }
+
+    /// Resolves the frame's queries into its readback buffer, after both passes.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, markers: &[&'static str]) {
+        let Some(active) = self.active.take() else {
+            return;
+        };
+
+        let markers = if self.inside_passes {
+            &markers[..markers.len().min(MAX_MARKERS)]
+        } else {
+            &[]
+        };
+        let count = FIRST_MARKER_QUERY + markers.len() as u32;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+
+        let readback = &mut self.readbacks[active];
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &readback.buffer,
+            0,
+            count as u64 * size_of::<u64>() as u64,
+        );
+        readback.markers = Some(markers.to_vec());
+        self.resolved = Some(active);
+    }
+
+    /// Starts reading the resolved queries back, once the frame has been submitted.
+    pub fn map_resolved(&mut self) {
+        let Some(resolved) = self.resolved.take() else {
+            return;
+        };
+
+        let mapped = self.readbacks[resolved].mapped.clone();
+        self.readbacks[resolved]
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if let Err(e) = &result {
+                    println!("Failed to read back timestamps: {e}");
+                }
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+    }
+}
+
+fn marker_query(marker: usize) -> Option<u32> {
+    (marker < MAX_MARKERS).then(|| FIRST_MARKER_QUERY + marker as u32)
+}
+
+fn timings(timestamps: &[u64], markers: &[&'static str], period: f32) -> GpuTimings {
+    let duration = |start: u32, end: u32| {
+        let ticks = timestamps[end as usize].saturating_sub(timestamps[start as usize]);
+        Duration::from_nanos((ticks as f64 * period as f64) as u64)
+    };
+
+    let ranges = markers
+        .iter()
+        .enumerate()
+        .map(|(i, label)| {
+            let start = FIRST_MARKER_QUERY + i as u32;
+            let end = if i + 1 < markers.len() {
+                start + 1
+            } else {
+                MAIN_PASS_QUERY + 1
+            };
+            (*label, duration(start, end))
+        })
+        .collect();
+
+    GpuTimings {
+        main_pass: duration(MAIN_PASS_QUERY, MAIN_PASS_QUERY + 1),
+        frame_buffer_pass: duration(FRAME_BUFFER_PASS_QUERY, FRAME_BUFFER_PASS_QUERY + 1),
+        ranges,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marker_queries_stay_inside_the_query_set() {
+        assert_eq!(marker_query(0), Some(FIRST_MARKER_QUERY));
+        assert_eq!(marker_query(MAX_MARKERS - 1), Some(MAX_QUERIES - 1));
+        assert_eq!(marker_query(MAX_MARKERS), None);
+    }
+
+    #[test]
+    fn full_marker_list_times_every_range() {
+        let timestamps: Vec<u64> = (0..MAX_QUERIES as u64).collect();
+        let markers = vec!["marker"; MAX_MARKERS];
+        let timings = timings(&timestamps, &markers, 1.0);
+        assert_eq!(timings.ranges.len(), MAX_MARKERS);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::app::GameRunner;
+use crate::config::AppConfig;
+use crate::renderer::RenderTarget;
+use crate::virtual_gpu::VirtualGpu;
+use crate::wgpu_setup;
+
+const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Runs the game without a window, rendering each tick into an offscreen
+/// texture. Ticks run back to back rather than in real time, until the
+/// playback ends or the frame limit is reached.
+pub fn run(config: &AppConfig, mut runner: GameRunner) {
+    if config.playback.is_none() && config.frames.is_none() {
+        println!("Headless mode needs --playback or --frames to know when to stop.");
+        return;
+    }
+
+    let backends = config.backend.backends();
+    let instance = wgpu_setup::create_gpu_instance(backends);
+    let adapter = match wgpu_setup::create_adapter(
+        &instance,
+        None,
+        backends,
+        config.force_fallback_adapter,
+    ) {
+        Ok(adapter) => adapter,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+    let (device, queue) = wgpu_setup::create_device(&adapter, config.optional_features());
+
+    let (width, height) = config.resolution.dimensions();
+    let display = config.display_settings();
+
+    // Stand in for a surface, only the format and size are used
+    let surface_config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: TARGET_FORMAT,
+        width,
+        height,
+        present_mode: display.present_mode,
+        alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+        view_formats: vec![],
+        desired_maximum_frame_latency: display.frame_latency,
+    };
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Headless Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TARGET_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    let mut virtual_gpu = VirtualGpu::new(device, queue, &surface_config, display, config);
+    runner.init(&mut virtual_gpu);
+
+    let mut frames = 0;
+    loop {
+        runner.tick(&mut virtual_gpu, &[]);
+        // Every tick is rendered, so there's nothing to interpolate
+        runner.draw(&mut virtual_gpu, 1.0);
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        virtual_gpu
+            .render(RenderTarget::Texture(target_view))
+            .unwrap();
+        frames += 1;
+
+        if runner.should_exit() || (config.frames.is_none() && runner.playback_finished()) {
+            break;
+        }
+    }
+
+    virtual_gpu.wait_for_frames();
+    virtual_gpu.device.poll(wgpu::Maintain::Wait);
+    runner.shutdown();
+    println!("Headless run finished after {frames} frames.");
+}
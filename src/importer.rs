@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use bytemuck::{cast_slice, from_bytes};
 use gltf::accessor::{DataType, Dimensions};
 
@@ -192,8 +194,9 @@ impl Importer {
     }
 }
 
-pub fn import_gltf(path: &str) -> Importer {
-    println!("Importing... {path}");
+pub fn import_gltf(path: impl AsRef<Path>) -> Importer {
+    let path = path.as_ref();
+    println!("Importing... {}", path.display());
     let (document, buffers, _images) = gltf::import(path).unwrap();
 
     let blob = &buffers[0].0;
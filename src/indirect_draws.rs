@@ -0,0 +1,98 @@
+use glam::Vec4;
+use wgpu::util::DrawIndexedIndirectArgs;
+
+use crate::pipeline::Pipeline;
+
+/// Requested when indirect draws are enabled. Draws need their own first
+/// instance, so indirect draws are only used where that's supported.
+pub const FEATURES: wgpu::Features =
+    wgpu::Features::INDIRECT_FIRST_INSTANCE.union(wgpu::Features::MULTI_DRAW_INDIRECT);
+
+// Indexed static mesh draws past this are drawn directly
+pub const MAX_INDIRECT_DRAWS: usize = 64 * 1024;
+
+/// Consecutive indexed draws from the same arena with the same pipeline,
+/// drawn with one indirect call.
+#[derive(Clone, Copy, Debug)]
+pub struct IndirectBatch {
+    pub pipeline: Pipeline,
+    pub arena: usize,
+    pub first: u32,
+    pub count: u32,
+}
+
+/// The indexed static mesh draws of a frame, in the order they're drawn.
+#[derive(Default)]
+pub struct IndirectDrawList {
+    pub args: Vec<DrawIndexedIndirectArgs>,
+    pub batches: Vec<IndirectBatch>,
+    // Per draw, which batch it's in and the mesh's bounding sphere for culling
+    pub draw_batches: Vec<u32>,
+    pub spheres: Vec<Vec4>,
+}
+
+/// Arguments of every indexed static mesh draw in a frame, written before the
+/// main pass. Runs of draws sharing a pipeline and arena are issued with one
+/// `multi_draw_indexed_indirect`, or a loop where that isn't supported.
+pub struct IndirectDraws {
+    pub buffer: wgpu::Buffer,
+    multi_draw: bool,
+}
+
+impl IndirectDraws {
+    pub fn new(device: &wgpu::Device) -> Option<Self> {
+        if !device
+            .features()
+            .contains(wgpu::Features::INDIRECT_FIRST_INSTANCE)
+        {
+            println!("Indirect first instance not supported, static meshes are drawn directly.");
+            return None;
+        }
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            size: (MAX_INDIRECT_DRAWS * size_of::<DrawIndexedIndirectArgs>()) as u64,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            buffer,
+            multi_draw: device
+                .features()
+                .contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+        })
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, draws: &[DrawIndexedIndirectArgs]) {
+        let bytes: Vec<u8> = draws
+            .iter()
+            .flat_map(|draw| draw.as_bytes())
+            .copied()
+            .collect();
+        queue.write_buffer(&self.buffer, 0, &bytes);
+    }
+
+    /// Issues `count` draws from `buffer` starting from the `first`, returning
+    /// the number of calls it took.
+    pub fn draw(
+        &self,
+        rp: &mut wgpu::RenderPass,
+        buffer: &wgpu::Buffer,
+        first: u32,
+        count: u32,
+    ) -> u32 {
+        let stride = size_of::<DrawIndexedIndirectArgs>() as u64;
+        if self.multi_draw {
+            rp.multi_draw_indexed_indirect(buffer, first as u64 * stride, count);
+            return 1;
+        }
+
+        for draw in first..first + count {
+            rp.draw_indexed_indirect(buffer, draw as u64 * stride);
+        }
+        count
+    }
+}
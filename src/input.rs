@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use winit::event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+// Pixel scroll deltas (ie from touchpads) are converted into lines
+const PIXELS_PER_LINE: f32 = 20.0;
+
+/// Names of the actions in the default binding set.
+pub mod actions {
+    pub const MOVE_FORWARD: &str = "move_forward";
+    pub const MOVE_BACK: &str = "move_back";
+    pub const MOVE_LEFT: &str = "move_left";
+    pub const MOVE_RIGHT: &str = "move_right";
+    pub const MOVE_UP: &str = "move_up";
+    pub const MOVE_DOWN: &str = "move_down";
+    pub const TURN_LEFT: &str = "turn_left";
+    pub const TURN_RIGHT: &str = "turn_right";
+    pub const MOUSE_LOOK: &str = "mouse_look";
+
+    pub const PLAY_CAMERA_PATH: &str = "play_camera_path";
+    pub const SHOW_CAMERA_PATH: &str = "show_camera_path";
+
+    pub const ENVIRONMENT_UP: &str = "environment_up";
+    pub const ENVIRONMENT_DOWN: &str = "environment_down";
+
+    pub const NEXT_SCENE: &str = "next_scene";
+    pub const CYCLE_WINDOW_MODE: &str = "cycle_window_mode";
+    pub const CYCLE_PRESENT_MODE: &str = "cycle_present_mode";
+    pub const CYCLE_FRAME_LATENCY: &str = "cycle_frame_latency";
+    pub const TOGGLE_STATS: &str = "toggle_stats";
+    pub const PRINT_RESOURCES: &str = "print_resources";
+    pub const TOGGLE_FRAME_DEBUGGER: &str = "toggle_frame_debugger";
+    pub const TOGGLE_CULLING_STATS: &str = "toggle_culling_stats";
+    pub const NEXT_COMMAND: &str = "next_command";
+    pub const PREVIOUS_COMMAND: &str = "previous_command";
+    pub const NEXT_DRAW: &str = "next_draw";
+    pub const PREVIOUS_DRAW: &str = "previous_draw";
+}
+
+/// Everything the input state is built from. These are what get recorded,
+/// so playing them back gives the same input each frame.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum InputEvent {
+    KeyDown(KeyCode),
+    KeyUp(KeyCode),
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    CursorMoved(f32, f32),
+    MouseMotion(f32, f32),
+    Scroll(f32, f32),
+    FocusLost,
+    CloseRequested,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+/// Input for the current frame, built from winit events. Pressed and released
+/// states, and the mouse & scroll deltas, only last for a single frame.
+pub struct InputState {
+    keys_down: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>,
+    keys_released: HashSet<KeyCode>,
+
+    mouse_down: HashSet<MouseButton>,
+    mouse_pressed: HashSet<MouseButton>,
+    mouse_released: HashSet<MouseButton>,
+
+    mouse_position: Vec2,
+    mouse_delta: Vec2,
+    scroll_delta: Vec2,
+
+    close_requested: bool,
+    frame_events: Vec<InputEvent>,
+
+    bindings: HashMap<String, Vec<Binding>>,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self {
+            keys_down: HashSet::new(),
+            keys_pressed: HashSet::new(),
+            keys_released: HashSet::new(),
+            mouse_down: HashSet::new(),
+            mouse_pressed: HashSet::new(),
+            mouse_released: HashSet::new(),
+            mouse_position: Vec2::ZERO,
+            mouse_delta: Vec2::ZERO,
+            scroll_delta: Vec2::ZERO,
+            close_requested: false,
+            frame_events: Vec::new(),
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// The default camera controls and app hotkeys.
+    pub fn with_default_bindings() -> Self {
+        let mut input = Self::new();
+
+        input.bind(actions::MOVE_FORWARD, Binding::Key(KeyCode::KeyW));
+        input.bind(actions::MOVE_BACK, Binding::Key(KeyCode::KeyS));
+        input.bind(actions::MOVE_LEFT, Binding::Key(KeyCode::KeyQ));
+        input.bind(actions::MOVE_RIGHT, Binding::Key(KeyCode::KeyE));
+        input.bind(actions::MOVE_UP, Binding::Key(KeyCode::KeyR));
+        input.bind(actions::MOVE_DOWN, Binding::Key(KeyCode::KeyF));
+        input.bind(actions::TURN_LEFT, Binding::Key(KeyCode::KeyA));
+        input.bind(actions::TURN_RIGHT, Binding::Key(KeyCode::KeyD));
+        input.bind(actions::MOUSE_LOOK, Binding::Mouse(MouseButton::Right));
+
+        input.bind(actions::PLAY_CAMERA_PATH, Binding::Key(KeyCode::KeyP));
+        input.bind(actions::SHOW_CAMERA_PATH, Binding::Key(KeyCode::KeyI));
+
+        input.bind(actions::ENVIRONMENT_UP, Binding::Key(KeyCode::KeyO));
+        input.bind(actions::ENVIRONMENT_DOWN, Binding::Key(KeyCode::KeyL));
+
+        input.bind(actions::NEXT_SCENE, Binding::Key(KeyCode::Tab));
+        input.bind(actions::CYCLE_WINDOW_MODE, Binding::Key(KeyCode::F11));
+        input.bind(actions::CYCLE_PRESENT_MODE, Binding::Key(KeyCode::F10));
+        input.bind(actions::CYCLE_FRAME_LATENCY, Binding::Key(KeyCode::F9));
+        input.bind(actions::TOGGLE_STATS, Binding::Key(KeyCode::F3));
+        input.bind(actions::PRINT_RESOURCES, Binding::Key(KeyCode::F4));
+        input.bind(actions::TOGGLE_FRAME_DEBUGGER, Binding::Key(KeyCode::F5));
+        input.bind(actions::TOGGLE_CULLING_STATS, Binding::Key(KeyCode::F6));
+        input.bind(actions::NEXT_COMMAND, Binding::Key(KeyCode::BracketRight));
+        input.bind(
+            actions::PREVIOUS_COMMAND,
+            Binding::Key(KeyCode::BracketLeft),
+        );
+        input.bind(actions::NEXT_DRAW, Binding::Key(KeyCode::PageDown));
+        input.bind(actions::PREVIOUS_DRAW, Binding::Key(KeyCode::PageUp));
+
+        input
+    }
+
+    /// Converts a winit window event into an input event, and applies it.
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        let event = match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(key) = event.physical_key else {
+                    return;
+                };
+
+                match event.state {
+                    ElementState::Pressed => InputEvent::KeyDown(key),
+                    ElementState::Released => InputEvent::KeyUp(key),
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => InputEvent::MouseDown(*button),
+                ElementState::Released => InputEvent::MouseUp(*button),
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                InputEvent::CursorMoved(position.x as f32, position.y as f32)
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => Vec2::new(*x, *y),
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        Vec2::new(delta.x as f32, delta.y as f32) / PIXELS_PER_LINE
+                    }
+                };
+                InputEvent::Scroll(delta.x, delta.y)
+            }
+            WindowEvent::Focused(false) => InputEvent::FocusLost,
+            WindowEvent::CloseRequested => InputEvent::CloseRequested,
+            _ => return,
+        };
+
+        self.apply(event);
+    }
+
+    /// Converts a winit device event into an input event, and applies it.
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.apply(InputEvent::MouseMotion(delta.0 as f32, delta.1 as f32));
+        }
+    }
+
+    /// Applies an event to the input state. Events are kept until the end of
+    /// the frame, so they can be recorded.
+    pub fn apply(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::KeyDown(key) => {
+                if self.keys_down.insert(key) {
+                    self.keys_pressed.insert(key);
+                }
+            }
+            InputEvent::KeyUp(key) => {
+                self.keys_down.remove(&key);
+                self.keys_released.insert(key);
+            }
+            InputEvent::MouseDown(button) => {
+                if self.mouse_down.insert(button) {
+                    self.mouse_pressed.insert(button);
+                }
+            }
+            InputEvent::MouseUp(button) => {
+                self.mouse_down.remove(&button);
+                self.mouse_released.insert(button);
+            }
+            InputEvent::CursorMoved(x, y) => self.mouse_position = Vec2::new(x, y),
+            InputEvent::MouseMotion(x, y) => self.mouse_delta += Vec2::new(x, y),
+            InputEvent::Scroll(x, y) => self.scroll_delta += Vec2::new(x, y),
+            InputEvent::FocusLost => {
+                // Release everything, otherwise keys get stuck down while unfocused
+                self.keys_released.extend(self.keys_down.drain());
+                self.mouse_released.extend(self.mouse_down.drain());
+            }
+            InputEvent::CloseRequested => self.close_requested = true,
+        }
+
+        self.frame_events.push(event);
+    }
+
+    /// Events applied since the start of the frame.
+    pub fn frame_events(&self) -> &[InputEvent] {
+        &self.frame_events
+    }
+
+    pub fn close_requested(&self) -> bool {
+        self.close_requested
+    }
+
+    /// Clears the per frame states, called after the game has been updated.
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.mouse_pressed.clear();
+        self.mouse_released.clear();
+        self.mouse_delta = Vec2::ZERO;
+        self.scroll_delta = Vec2::ZERO;
+        self.frame_events.clear();
+    }
+
+    pub fn key_down(&self, key: KeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn key_released(&self, key: KeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn mouse_down(&self, button: MouseButton) -> bool {
+        self.mouse_down.contains(&button)
+    }
+
+    pub fn mouse_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_pressed.contains(&button)
+    }
+
+    pub fn mouse_released(&self, button: MouseButton) -> bool {
+        self.mouse_released.contains(&button)
+    }
+
+    /// Cursor position in window pixels.
+    pub fn mouse_position(&self) -> Vec2 {
+        self.mouse_position
+    }
+
+    /// Raw mouse movement since the last frame, unaffected by the cursor position.
+    pub fn mouse_delta(&self) -> Vec2 {
+        self.mouse_delta
+    }
+
+    /// Scroll since the last frame, in lines.
+    pub fn scroll_delta(&self) -> Vec2 {
+        self.scroll_delta
+    }
+
+    /// Adds a binding to an action, an action can have multiple bindings.
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        self.bindings
+            .entry(action.to_string())
+            .or_default()
+            .push(binding);
+    }
+
+    #[allow(dead_code)]
+    pub fn unbind(&mut self, action: &str) {
+        self.bindings.remove(action);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn action_down(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|binding| match binding {
+            Binding::Key(key) => self.key_down(*key),
+            Binding::Mouse(button) => self.mouse_down(*button),
+        })
+    }
+
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|binding| match binding {
+            Binding::Key(key) => self.key_pressed(*key),
+            Binding::Mouse(button) => self.mouse_pressed(*button),
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn action_released(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|binding| match binding {
+            Binding::Key(key) => self.key_released(*key),
+            Binding::Mouse(button) => self.mouse_released(*button),
+        })
+    }
+
+    /// Returns -1, 0 or 1 depending on which of the two actions are held.
+    pub fn action_axis(&self, negative: &str, positive: &str) -> f32 {
+        let mut axis = 0.0;
+        if self.action_down(negative) {
+            axis -= 1.0;
+        }
+        if self.action_down(positive) {
+            axis += 1.0;
+        }
+        axis
+    }
+}
@@ -1,7 +1,6 @@
 use bytemuck::{Pod, Zeroable};
 use glam::Vec4;
 
-pub type LightUniformType = [f32; 12];
 pub const MAX_LIGHTS: u64 = 4;
 
 #[derive(Pod, Zeroable, Clone, Copy)]
@@ -12,12 +11,6 @@ pub struct Light {
     pub direction_min_angle: Vec4,
 }
 
-impl Light {
-    pub fn get_light_uniforms(&self) -> LightUniformType {
-        bytemuck::cast(*self)
-    }
-}
-
 pub struct Lights {
     pub buffer: wgpu::Buffer,
 }
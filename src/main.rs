@@ -1,41 +1,57 @@
-use pollster::block_on;
-use winit::event_loop::EventLoop;
-
 mod app;
 mod camera;
+mod camera_controllers;
+mod camera_path;
+mod command_list;
+mod config;
 mod contexts;
+mod display;
+mod draw_sorting;
 mod environment_map;
 mod frame_buffer;
+mod frame_debugger;
+mod frame_stats;
 mod game;
+mod gpu_culling;
+mod gpu_timer;
+mod headless;
 mod immediate_renderer;
 mod importer;
+mod indirect_draws;
+mod input;
 mod lights;
 mod mesh;
+mod mesh_pool;
+mod object_ids;
+mod picking;
 mod pipeline;
 mod preloaded_renderer;
 mod quad_renderer;
+mod recording;
+mod render_thread;
+mod renderer;
 mod resolution;
+mod resources;
+mod scenes;
 mod spec_tex;
+mod stats_overlay;
 mod textures;
+mod time;
 mod vertex;
 mod virtual_gpu;
 mod virtual_render_pass;
 mod wgpu_setup;
 
-use app::StateApplication;
+use app::App;
+use config::AppConfig;
+use scenes::{MatcapGallery, PbrTest};
 
 fn main() {
     env_logger::init();
 
     //spec_tex::generate_texture();
 
-    block_on(run());
-}
-
-pub async fn run() {
-    let event_loop = EventLoop::new().unwrap();
-    event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
-
-    let mut window_state = StateApplication::new(120.0);
-    let _ = event_loop.run_app(&mut window_state);
+    App::new(AppConfig::load())
+        .register_scene(Box::new(PbrTest::new()))
+        .run(Box::new(MatcapGallery::new()));
 }
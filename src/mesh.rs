@@ -1,16 +1,57 @@
-use crate::pipeline::Pipeline;
+use std::ops::Range;
 
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::{camera::Frustum, pipeline::Pipeline};
+
+/// A mesh's place in the mesh pool, its vertices are in the arena for its
+/// pipeline's vertex size.
 pub struct Mesh {
-    pub vertex_buffer: wgpu::Buffer,
+    pub arena: usize,
+    pub first_vertex: u32,
     pub vertex_count: u32,
     pub pipeline: Pipeline,
+
+    // CPU copy of the uploaded data, used to rebuild the mesh if the device is lost
+    pub data: Vec<f32>,
+    // Only kept when cpu picking is enabled
+    pub positions: Option<Vec<Vec3>>,
+    pub bounds: Bounds,
+    // Where the data came from, if the game said so
+    pub source: Option<String>,
+}
+
+impl Mesh {
+    pub fn vertices(&self) -> Range<u32> {
+        self.first_vertex..self.first_vertex + self.vertex_count
+    }
 }
 
+/// Indices are relative to the mesh's first vertex, drawn with it as the base vertex.
 pub struct IndexedMesh {
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub arena: usize,
+    pub base_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
     pub index_count: u32,
     pub pipeline: Pipeline,
+
+    // CPU copy of the uploaded data, used to rebuild the mesh if the device is lost
+    pub data: Vec<f32>,
+    pub indices: Vec<u16>,
+    pub positions: Option<Vec<Vec3>>,
+    pub bounds: Bounds,
+    pub source: Option<String>,
+}
+
+impl IndexedMesh {
+    pub fn vertices(&self) -> Range<u32> {
+        self.base_vertex..self.base_vertex + self.vertex_count
+    }
+
+    pub fn index_range(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
 }
 
 pub fn quad_vertex_buffer_descriptor() -> wgpu::BufferDescriptor<'static> {
@@ -66,3 +107,136 @@ pub fn index_buffer_descriptor(
         mapped_at_creation: false,
     }
 }
+
+/// Pulls the positions out of interleaved vertex data, they're always the first attribute.
+pub fn positions(data: &[f32], pipeline: Pipeline) -> Vec<Vec3> {
+    data.chunks_exact(pipeline.get_attribute_count())
+        .map(Vec3::from_slice)
+        .collect()
+}
+
+/// Box and sphere around a mesh's vertices, in its own space. The sphere is
+/// centered on the box.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Bounds {
+    pub fn new(data: &[f32], pipeline: Pipeline) -> Self {
+        let positions = positions(data, pipeline);
+        let Some(first) = positions.first() else {
+            return Self::default();
+        };
+        let (min, max) = positions
+            .iter()
+            .fold((*first, *first), |(min, max), position| {
+                (min.min(*position), max.max(*position))
+            });
+        let center = (min + max) / 2.0;
+        let radius = positions
+            .iter()
+            .map(|position| position.distance(center))
+            .fold(0.0, f32::max);
+        Self {
+            min,
+            max,
+            center,
+            radius,
+        }
+    }
+
+    /// Center and radius, as the gpu culling pass takes them.
+    pub fn sphere(&self) -> Vec4 {
+        self.center.extend(self.radius)
+    }
+
+    /// Whether any of the mesh may be in the frustum, drawn with `matrix`.
+    pub fn is_visible(&self, frustum: &Frustum, matrix: Mat4) -> bool {
+        let scale = matrix
+            .x_axis
+            .truncate()
+            .length()
+            .max(matrix.y_axis.truncate().length())
+            .max(matrix.z_axis.truncate().length());
+        if !frustum.intersects_sphere(matrix.transform_point3(self.center), self.radius * scale) {
+            return false;
+        }
+
+        // The box is tested in the mesh's space, against the corner furthest along each plane
+        let transpose = matrix.transpose();
+        frustum.planes.iter().all(|plane| {
+            let plane = transpose * *plane;
+            let corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), self.max, self.min);
+            plane.dot(corner.extend(1.0)) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::Quat;
+
+    use super::*;
+
+    // Looking down -z with a 90 degree fov, so the sides are at |x| = -z and |y| = -z
+    fn frustum() -> Frustum {
+        Frustum::from_view_projection(Mat4::perspective_infinite_reverse_rh(FRAC_PI_2, 1.0, 0.1))
+    }
+
+    fn unit_cube() -> Bounds {
+        Bounds {
+            min: Vec3::splat(-1.0),
+            max: Vec3::splat(1.0),
+            center: Vec3::ZERO,
+            radius: 3.0f32.sqrt(),
+        }
+    }
+
+    #[test]
+    fn inside_is_visible() {
+        let matrix = Mat4::from_translation(Vec3::new(0.0, 0.0, -10.0));
+        assert!(unit_cube().is_visible(&frustum(), matrix));
+    }
+
+    #[test]
+    fn outside_is_culled() {
+        let bounds = unit_cube();
+        for position in [
+            Vec3::new(0.0, 0.0, 10.0),
+            Vec3::new(13.0, 0.0, -10.0),
+            Vec3::new(0.0, -13.0, -10.0),
+        ] {
+            let matrix = Mat4::from_translation(position);
+            assert!(!bounds.is_visible(&frustum(), matrix), "{position}");
+        }
+    }
+
+    #[test]
+    fn straddling_is_visible() {
+        let bounds = unit_cube();
+        let matrix = Mat4::from_translation(Vec3::new(10.5, 0.0, -10.0));
+        assert!(bounds.is_visible(&frustum(), matrix));
+        // Only in view once scaled up
+        let position = Vec3::new(14.0, 0.0, -10.0);
+        assert!(!bounds.is_visible(&frustum(), Mat4::from_translation(position)));
+        let matrix =
+            Mat4::from_scale_rotation_translation(Vec3::splat(5.0), Quat::IDENTITY, position);
+        assert!(bounds.is_visible(&frustum(), matrix));
+    }
+
+    #[test]
+    fn box_outside_is_culled_when_its_sphere_is_not() {
+        // The left side is diagonal to the box, so the sphere reaches into view where the box doesn't
+        let matrix = Mat4::from_translation(Vec3::new(-12.25, 0.0, -10.0));
+        let bounds = unit_cube();
+        let center = matrix.transform_point3(bounds.center);
+        assert!(frustum().intersects_sphere(center, bounds.radius));
+        assert!(!bounds.is_visible(&frustum(), matrix));
+    }
+}
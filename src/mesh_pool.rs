@@ -0,0 +1,321 @@
+use std::ops::Range;
+
+use bytemuck::cast_slice;
+
+// Starting sizes of an arena, in vertices and indices. Arenas double when full
+const INITIAL_VERTICES: u32 = 64 * 1024;
+const INITIAL_INDICES: u32 = 256 * 1024;
+
+/// First fit allocator of element ranges. Freed ranges are merged with their
+/// neighbours, so space from unloaded meshes can be reused by larger ones.
+pub struct RangeAllocator {
+    capacity: u32,
+    // Sorted by start, never empty ranges
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u32) -> Self {
+        let mut allocator = Self {
+            capacity,
+            free: Vec::new(),
+        };
+        allocator.free(0..capacity);
+        allocator
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn allocate(&mut self, count: u32) -> Option<Range<u32>> {
+        if count == 0 {
+            return Some(0..0);
+        }
+
+        let index = self
+            .free
+            .iter()
+            .position(|range| range.end - range.start >= count)?;
+        let range = &mut self.free[index];
+        let start = range.start;
+        range.start += count;
+        if range.start == range.end {
+            self.free.remove(index);
+        }
+        Some(start..start + count)
+    }
+
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let index = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(index, range);
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+
+    /// Adds free space at the end, once the buffer behind it has grown.
+    fn grow(&mut self, capacity: u32) {
+        let old_capacity = self.capacity;
+        self.capacity = capacity;
+        self.free(old_capacity..capacity);
+    }
+}
+
+/// Vertex and index buffers shared by every mesh with the same vertex size.
+/// Meshes are drawn from their offsets, so consecutive draws from the same
+/// arena don't rebind anything.
+pub struct MeshArena {
+    pub vertex_size: u32,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    vertices: RangeAllocator,
+    indices: RangeAllocator,
+}
+
+impl MeshArena {
+    fn new(device: &wgpu::Device, vertex_size: u32) -> Self {
+        Self {
+            vertex_size,
+            vertex_buffer: create_buffer(
+                device,
+                INITIAL_VERTICES as u64 * vertex_size as u64,
+                wgpu::BufferUsages::VERTEX,
+            ),
+            index_buffer: create_buffer(
+                device,
+                INITIAL_INDICES as u64 * size_of::<u16>() as u64,
+                wgpu::BufferUsages::INDEX,
+            ),
+            vertices: RangeAllocator::new(INITIAL_VERTICES),
+            indices: RangeAllocator::new(INITIAL_INDICES),
+        }
+    }
+
+    /// Copies the vertices into free space, growing the arena if there's none.
+    /// Returns where they were put, in vertices.
+    pub fn upload_vertices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[f32],
+    ) -> Range<u32> {
+        let count = (size_of_val(data) / self.vertex_size as usize) as u32;
+        let range = match self.vertices.allocate(count) {
+            Some(range) => range,
+            None => {
+                let capacity = grown_capacity(self.vertices.capacity(), count);
+                self.vertex_buffer = grow_buffer(
+                    device,
+                    queue,
+                    &self.vertex_buffer,
+                    capacity as u64 * self.vertex_size as u64,
+                    wgpu::BufferUsages::VERTEX,
+                );
+                self.vertices.grow(capacity);
+                self.vertices.allocate(count).unwrap()
+            }
+        };
+
+        let offset = range.start as u64 * self.vertex_size as u64;
+        queue.write_buffer(&self.vertex_buffer, offset, cast_slice(data));
+        range
+    }
+
+    /// Copies the indices into free space, growing the arena if there's none.
+    /// Returns where they were put, in indices.
+    pub fn upload_indices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        indices: &[u16],
+    ) -> Range<u32> {
+        let count = indices.len() as u32;
+        let allocated = index_allocation(count);
+        let range = match self.indices.allocate(allocated) {
+            Some(range) => range,
+            None => {
+                let capacity = grown_capacity(self.indices.capacity(), allocated);
+                self.index_buffer = grow_buffer(
+                    device,
+                    queue,
+                    &self.index_buffer,
+                    capacity as u64 * size_of::<u16>() as u64,
+                    wgpu::BufferUsages::INDEX,
+                );
+                self.indices.grow(capacity);
+                self.indices.allocate(allocated).unwrap()
+            }
+        };
+
+        // Writes must be a multiple of 4 bytes, so odd counts get a padding index
+        let offset = range.start as u64 * size_of::<u16>() as u64;
+        if count == allocated {
+            queue.write_buffer(&self.index_buffer, offset, cast_slice(indices));
+        } else {
+            let mut padded = indices.to_vec();
+            padded.push(0);
+            queue.write_buffer(&self.index_buffer, offset, cast_slice(&padded));
+        }
+        range.start..range.start + count
+    }
+
+    pub fn free_vertices(&mut self, vertices: Range<u32>) {
+        self.vertices.free(vertices);
+    }
+
+    pub fn free_indices(&mut self, indices: Range<u32>) {
+        let count = index_allocation(indices.end - indices.start);
+        self.indices.free(indices.start..indices.start + count);
+    }
+}
+
+/// The space a mesh held in its arena, kept until it can be freed.
+pub struct MeshRanges {
+    pub arena: usize,
+    pub vertices: Range<u32>,
+    pub indices: Option<Range<u32>>,
+}
+
+/// Every arena, one per vertex size.
+#[derive(Default)]
+pub struct MeshPool {
+    pub arenas: Vec<MeshArena>,
+}
+
+impl MeshPool {
+    /// The arena for vertices of this size, created the first time it's needed.
+    pub fn arena(&mut self, device: &wgpu::Device, vertex_size: u32) -> usize {
+        if let Some(index) = self
+            .arenas
+            .iter()
+            .position(|arena| arena.vertex_size == vertex_size)
+        {
+            return index;
+        }
+
+        self.arenas.push(MeshArena::new(device, vertex_size));
+        self.arenas.len() - 1
+    }
+
+    pub fn free(&mut self, ranges: MeshRanges) {
+        let arena = &mut self.arenas[ranges.arena];
+        arena.free_vertices(ranges.vertices);
+        if let Some(indices) = ranges.indices {
+            arena.free_indices(indices);
+        }
+    }
+
+    pub fn vertex_bytes(&self) -> u64 {
+        self.arenas
+            .iter()
+            .map(|arena| arena.vertex_buffer.size())
+            .sum()
+    }
+
+    pub fn index_bytes(&self) -> u64 {
+        self.arenas
+            .iter()
+            .map(|arena| arena.index_buffer.size())
+            .sum()
+    }
+}
+
+// Index ranges are kept to an even length, so every one starts 4 byte aligned
+fn index_allocation(count: u32) -> u32 {
+    count.next_multiple_of(2)
+}
+
+fn grown_capacity(capacity: u32, needed: u32) -> u32 {
+    (capacity * 2).max(capacity + needed)
+}
+
+fn create_buffer(device: &wgpu::Device, size: u64, usage: wgpu::BufferUsages) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Mesh Arena"),
+        size,
+        usage: usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+// Frames already submitted keep drawing from the old buffer until they finish
+fn grow_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    size: u64,
+    usage: wgpu::BufferUsages,
+) -> wgpu::Buffer {
+    let grown = create_buffer(device, size, usage);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mesh Arena Growth"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &grown, 0, buffer.size());
+    queue.submit(std::iter::once(encoder.finish()));
+    grown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_in_order_until_full() {
+        let mut allocator = RangeAllocator::new(10);
+        assert_eq!(allocator.allocate(4), Some(0..4));
+        assert_eq!(allocator.allocate(6), Some(4..10));
+        assert_eq!(allocator.allocate(1), None);
+        assert_eq!(allocator.allocate(0), Some(0..0));
+    }
+
+    #[test]
+    fn reuses_the_first_range_that_fits() {
+        let mut allocator = RangeAllocator::new(20);
+        let a = allocator.allocate(4).unwrap();
+        let _b = allocator.allocate(4).unwrap();
+        let c = allocator.allocate(8).unwrap();
+        let _d = allocator.allocate(4).unwrap();
+
+        allocator.free(a);
+        allocator.free(c);
+        // Too big for the first hole, so it goes in the second
+        assert_eq!(allocator.allocate(6), Some(8..14));
+        assert_eq!(allocator.allocate(3), Some(0..3));
+    }
+
+    #[test]
+    fn merges_freed_neighbours() {
+        let mut allocator = RangeAllocator::new(12);
+        let a = allocator.allocate(4).unwrap();
+        let b = allocator.allocate(4).unwrap();
+        let c = allocator.allocate(4).unwrap();
+
+        allocator.free(a);
+        allocator.free(c);
+        assert_eq!(allocator.free, vec![0..4, 8..12]);
+        allocator.free(b);
+        assert_eq!(allocator.free, vec![0..12]);
+        assert_eq!(allocator.allocate(12), Some(0..12));
+    }
+
+    #[test]
+    fn grows_into_the_free_space_at_the_end() {
+        let mut allocator = RangeAllocator::new(8);
+        allocator.allocate(6).unwrap();
+        assert_eq!(allocator.allocate(4), None);
+
+        allocator.grow(16);
+        assert_eq!(allocator.capacity(), 16);
+        // The old tail merges with the new space
+        assert_eq!(allocator.allocate(4), Some(6..10));
+        assert_eq!(allocator.free, vec![10..16]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::{Arc, Mutex};
+
+pub const OBJECT_ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+// Texture copies need rows aligned to 256 bytes, even for a single pixel
+const READBACK_SIZE: u64 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+
+/// Optional second attachment of the main pass, holding the object id drawn
+/// at each pixel. Ids are read back asynchronously, so a pick resolves a frame
+/// or two after it's requested. 0 means nothing was drawn.
+pub struct ObjectIds {
+    pub view: wgpu::TextureView,
+    texture: wgpu::Texture,
+    width: u32,
+    height: u32,
+
+    readback_buffer: wgpu::Buffer,
+    requested: Option<(u32, u32)>,
+    copied: bool,
+    in_flight: bool,
+    // Set by the map callback, to whether the read succeeded
+    mapped: Arc<Mutex<Option<bool>>>,
+    last_pick: Option<u32>,
+}
+
+impl ObjectIds {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Object Id Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OBJECT_ID_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Object Id Readback Buffer"),
+            size: READBACK_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            view,
+            texture,
+            width,
+            height,
+            readback_buffer,
+            requested: None,
+            copied: false,
+            in_flight: false,
+            mapped: Arc::new(Mutex::new(None)),
+            last_pick: None,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn readback_size(&self) -> u64 {
+        self.readback_buffer.size()
+    }
+
+    pub fn last_pick(&self) -> Option<u32> {
+        self.last_pick
+    }
+
+    /// Asks for the id at a frame buffer pixel to be read back after the next
+    /// frame. Returns the result of the last finished read.
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<u32> {
+        if x < self.width && y < self.height {
+            self.requested = Some((x, y));
+        }
+        self.last_pick
+    }
+
+    /// Picks up a finished read, called before the frame is encoded.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if !self.in_flight {
+            return;
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        let Some(success) = self.mapped.lock().unwrap().take() else {
+            return;
+        };
+        self.in_flight = false;
+        if !success {
+            return;
+        }
+
+        let id = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+        };
+        self.readback_buffer.unmap();
+        self.last_pick = (id != 0).then_some(id);
+    }
+
+    /// Copies the requested pixel out, after the main pass. Only one read is
+    /// in flight at a time, newer requests wait for it to finish.
+    pub fn copy_requested(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.in_flight {
+            return;
+        }
+        let Some((x, y)) = self.requested.take() else {
+            return;
+        };
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(READBACK_SIZE as u32),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.copied = true;
+    }
+
+    /// Starts mapping the copied pixel, once the frame has been submitted.
+    pub fn map_copied(&mut self) {
+        if !self.copied {
+            return;
+        }
+        self.copied = false;
+        self.in_flight = true;
+
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if let Err(e) = &result {
+                    println!("Failed to read back object id: {e}");
+                }
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+    }
+}
@@ -0,0 +1,267 @@
+use glam::{Mat4, Vec3};
+
+use crate::preloaded_renderer::PreloadedRenderer;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Normalized, unless the ray was moved into a scaled model space.
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    pub fn transform(&self, matrix: Mat4) -> Ray {
+        Ray {
+            origin: matrix.transform_point3(self.origin),
+            direction: matrix.transform_vector3(self.direction),
+        }
+    }
+
+    /// Möller-Trumbore, returns the distance along the ray in units of the
+    /// direction. Both sides of the triangle are hit.
+    pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+        const EPSILON: f32 = 1e-7;
+
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < EPSILON {
+            return None;
+        }
+
+        let inverse = 1.0 / determinant;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = to_origin.cross(edge1);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(q) * inverse;
+        (distance > EPSILON).then_some(distance)
+    }
+}
+
+/// A mesh id, as returned by `load_static_mesh` or `load_static_mesh_indexed`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MeshHandle {
+    Static(usize),
+    Indexed(usize),
+}
+
+/// A static mesh draw, kept from the last frame so it can be picked.
+#[derive(Clone, Copy, Debug)]
+pub struct DrawnMesh {
+    pub mesh: MeshHandle,
+    /// Index of the model matrix in the frame's instance buffer.
+    pub instance: u32,
+    pub matrix: Mat4,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub mesh: MeshHandle,
+    pub instance: u32,
+    pub point: Vec3,
+    pub distance: f32,
+    /// Index of the first vertex of the triangle, in the mesh's index list for
+    /// indexed meshes, or its vertex list otherwise.
+    pub triangle_index: u32,
+    /// The triangle's corners in world space.
+    pub triangle: [Vec3; 3],
+}
+
+/// Finds the closest triangle hit by the ray, among the meshes drawn. Meshes
+/// loaded without cpu positions are skipped.
+pub fn pick(ray: &Ray, drawn: &[DrawnMesh], renderer: &PreloadedRenderer) -> Option<RayHit> {
+    let mut closest: Option<RayHit> = None;
+
+    for draw in drawn.iter() {
+        let (positions, indices) = match draw.mesh {
+            MeshHandle::Static(index) => match &renderer.meshes[index] {
+                Some(mesh) => (mesh.positions.as_deref(), None),
+                None => continue,
+            },
+            MeshHandle::Indexed(index) => match &renderer.indexed_meshes[index] {
+                Some(mesh) => (mesh.positions.as_deref(), Some(mesh.indices.as_slice())),
+                None => continue,
+            },
+        };
+        let Some(positions) = positions else {
+            continue;
+        };
+
+        // Testing in model space avoids transforming every vertex
+        let local_ray = ray.transform(draw.matrix.inverse());
+
+        let triangle_count = indices.map_or(positions.len(), <[u16]>::len) / 3;
+        for triangle in 0..triangle_count {
+            let corner = |n: usize| {
+                let vertex = triangle * 3 + n;
+                let vertex = indices.map_or(vertex, |indices| indices[vertex] as usize);
+                positions[vertex]
+            };
+            let (a, b, c) = (corner(0), corner(1), corner(2));
+
+            let Some(t) = local_ray.intersect_triangle(a, b, c) else {
+                continue;
+            };
+
+            let point = draw.matrix.transform_point3(local_ray.at(t));
+            let distance = point.distance(ray.origin);
+            if closest.is_some_and(|hit| hit.distance <= distance) {
+                continue;
+            }
+
+            closest = Some(RayHit {
+                mesh: draw.mesh,
+                instance: draw.instance,
+                point,
+                distance,
+                triangle_index: triangle as u32 * 3,
+                triangle: [a, b, c].map(|corner| draw.matrix.transform_point3(corner)),
+            });
+        }
+    }
+
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mesh::{Bounds, IndexedMesh, Mesh};
+    use crate::pipeline::Pipeline;
+
+    use super::*;
+
+    // A triangle around the origin, facing +z
+    const A: Vec3 = Vec3::new(-1.0, -1.0, 0.0);
+    const B: Vec3 = Vec3::new(1.0, -1.0, 0.0);
+    const C: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray { origin, direction }
+    }
+
+    // The same triangle loaded as a static mesh, and as an indexed one
+    fn renderer() -> PreloadedRenderer {
+        let data: Vec<f32> = [A, B, C]
+            .iter()
+            .flat_map(|corner| [corner.x, corner.y, corner.z, 1.0, 1.0, 1.0])
+            .collect();
+        let bounds = Bounds::new(&data, Pipeline::Color);
+
+        let mut renderer = PreloadedRenderer::new(true);
+        renderer.meshes.push(Some(Mesh {
+            arena: 0,
+            first_vertex: 0,
+            vertex_count: 3,
+            pipeline: Pipeline::Color,
+            data: data.clone(),
+            positions: Some(vec![A, B, C]),
+            bounds,
+            source: None,
+        }));
+        renderer.indexed_meshes.push(Some(IndexedMesh {
+            arena: 0,
+            base_vertex: 0,
+            vertex_count: 3,
+            first_index: 0,
+            index_count: 3,
+            pipeline: Pipeline::Color,
+            data,
+            indices: vec![2, 0, 1],
+            positions: Some(vec![A, B, C]),
+            bounds,
+            source: None,
+        }));
+        renderer
+    }
+
+    #[test]
+    fn triangle_hit() {
+        let distance = ray(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z).intersect_triangle(A, B, C);
+        assert_eq!(distance, Some(5.0));
+    }
+
+    #[test]
+    fn triangle_miss() {
+        let beside = ray(Vec3::new(2.0, 0.0, 5.0), Vec3::NEG_Z);
+        assert_eq!(beside.intersect_triangle(A, B, C), None);
+
+        let away = ray(Vec3::new(0.0, 0.0, 5.0), Vec3::Z);
+        assert_eq!(away.intersect_triangle(A, B, C), None);
+    }
+
+    #[test]
+    fn triangle_hit_from_behind() {
+        let distance = ray(Vec3::new(0.0, 0.0, -5.0), Vec3::Z).intersect_triangle(A, B, C);
+        assert_eq!(distance, Some(5.0));
+    }
+
+    #[test]
+    fn parallel_ray_misses() {
+        for origin in [Vec3::new(-5.0, 0.0, 0.0), Vec3::new(-5.0, 0.0, 1.0)] {
+            let parallel = ray(origin, Vec3::X);
+            assert_eq!(parallel.intersect_triangle(A, B, C), None, "{origin}");
+        }
+    }
+
+    #[test]
+    fn pick_returns_closest_mesh() {
+        let renderer = renderer();
+        // The further mesh is drawn first, so the order doesn't decide the hit
+        let drawn = [
+            DrawnMesh {
+                mesh: MeshHandle::Indexed(0),
+                instance: 0,
+                matrix: Mat4::from_translation(Vec3::new(0.0, 0.0, -2.0)),
+            },
+            DrawnMesh {
+                mesh: MeshHandle::Static(0),
+                instance: 1,
+                matrix: Mat4::from_scale(Vec3::splat(0.5)),
+            },
+        ];
+
+        let hit = pick(
+            &ray(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z),
+            &drawn,
+            &renderer,
+        )
+        .unwrap();
+        assert_eq!(hit.mesh, MeshHandle::Static(0));
+        assert_eq!(hit.instance, 1);
+        assert_eq!(hit.point, Vec3::ZERO);
+        assert_eq!(hit.distance, 5.0);
+        assert_eq!(hit.triangle_index, 0);
+        assert_eq!(hit.triangle, [A, B, C].map(|corner| corner * 0.5));
+
+        // Above the closer, smaller triangle only the further one is hit
+        let hit = pick(
+            &ray(Vec3::new(0.0, 0.75, 5.0), Vec3::NEG_Z),
+            &drawn,
+            &renderer,
+        )
+        .unwrap();
+        assert_eq!(hit.mesh, MeshHandle::Indexed(0));
+        assert_eq!(hit.distance, 7.0);
+
+        let miss = pick(
+            &ray(Vec3::new(5.0, 0.0, 5.0), Vec3::NEG_Z),
+            &drawn,
+            &renderer,
+        );
+        assert!(miss.is_none());
+    }
+}
@@ -71,8 +71,12 @@ impl Pipeline {
         color && uv && lighting
     }
 
-    pub fn get_pipeline_buffers(&self) -> [wgpu::VertexBufferLayout<'static>; 2] {
-        [self.get_vertex_buffer_layout(), vertex::model_matrix()]
+    pub fn get_pipeline_buffers(&self) -> [wgpu::VertexBufferLayout<'static>; 3] {
+        [
+            self.get_vertex_buffer_layout(),
+            vertex::model_matrix(),
+            vertex::object_id(),
+        ]
     }
 
     pub fn get_vertex_buffer_layout(&self) -> wgpu::VertexBufferLayout<'static> {
@@ -123,6 +127,22 @@ impl Pipeline {
         }
     }
 
+    pub fn has_matcap(&self) -> bool {
+        match self {
+            Pipeline::Color => false,
+            Pipeline::Uv => false,
+            Pipeline::ColorUv => false,
+            Pipeline::ColorLit => false,
+            Pipeline::UvLit => false,
+            Pipeline::ColorUvLit => false,
+            Pipeline::Quad2d => false,
+            Pipeline::Matcap => true,
+            Pipeline::MatcapColor => true,
+            Pipeline::MatcapUv => true,
+            Pipeline::MatcapColorUv => true,
+        }
+    }
+
     pub fn has_lighting(&self) -> bool {
         match self {
             Pipeline::Color => false,
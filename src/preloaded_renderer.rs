@@ -1,20 +1,28 @@
-use bytemuck::cast_slice;
-
 use crate::{
-    mesh::{self, IndexedMesh, Mesh},
+    mesh::{self, Bounds, IndexedMesh, Mesh},
+    mesh_pool::{MeshPool, MeshRanges},
+    picking::MeshHandle,
     pipeline::Pipeline,
 };
 
+/// Static meshes, sub-allocated from a pool of arenas. Unloaded meshes leave
+/// their slot empty so other ids stay valid, and their space is reused.
 pub struct PreloadedRenderer {
-    pub meshes: Vec<Mesh>,
-    pub indexed_meshes: Vec<IndexedMesh>,
+    pub meshes: Vec<Option<Mesh>>,
+    pub indexed_meshes: Vec<Option<IndexedMesh>>,
+    pub pool: MeshPool,
+
+    /// Keeps the positions of loaded meshes on the cpu, so they can be picked.
+    pub keep_positions: bool,
 }
 
 impl PreloadedRenderer {
-    pub fn new() -> Self {
+    pub fn new(keep_positions: bool) -> Self {
         Self {
             meshes: Vec::new(),
             indexed_meshes: Vec::new(),
+            pool: MeshPool::default(),
+            keep_positions,
         }
     }
 
@@ -27,25 +35,26 @@ impl PreloadedRenderer {
     ) -> usize {
         let attribute_count = pipeline.get_attribute_count();
         let total_attributes = data.len();
-        let vertex_count = total_attributes / attribute_count;
-        let bytes = vertex_count * attribute_count * 4;
 
-        if total_attributes % attribute_count != 0 {
+        if !total_attributes.is_multiple_of(attribute_count) {
             panic!("Invalid mesh list, size mismatch");
         }
 
-        let vertex_buffer =
-            device.create_buffer(&mesh::vertex_buffer_descriptor(bytes as u64, None));
-
-        queue.write_buffer(&vertex_buffer, 0, cast_slice(data));
+        let arena = self.pool.arena(device, pipeline.get_vertex_size() as u32);
+        let vertices = self.pool.arenas[arena].upload_vertices(device, queue, data);
 
         let mesh = Mesh {
-            vertex_buffer,
+            arena,
+            first_vertex: vertices.start,
+            vertex_count: vertices.len() as u32,
             pipeline,
-            vertex_count: vertex_count as u32,
+            data: data.to_vec(),
+            positions: self.keep_positions.then(|| mesh::positions(data, pipeline)),
+            bounds: Bounds::new(data, pipeline),
+            source: None,
         };
 
-        self.meshes.push(mesh);
+        self.meshes.push(Some(mesh));
         self.meshes.len() - 1
     }
 
@@ -59,30 +68,121 @@ impl PreloadedRenderer {
     ) -> usize {
         let attribute_count = pipeline.get_attribute_count();
         let total_attributes = data.len();
-        let vertex_count = total_attributes / attribute_count;
-        let bytes = vertex_count * attribute_count * 4;
 
-        if total_attributes % attribute_count != 0 {
+        if !total_attributes.is_multiple_of(attribute_count) {
             panic!("Invalid mesh list, size mismatch");
         }
 
-        let vertex_buffer =
-            device.create_buffer(&mesh::vertex_buffer_descriptor(bytes as u64, None));
-
-        let bytes = std::mem::size_of_val(indices);
-        let index_buffer = device.create_buffer(&mesh::index_buffer_descriptor(bytes as u64, None));
-
-        queue.write_buffer(&vertex_buffer, 0, cast_slice(data));
-        queue.write_buffer(&index_buffer, 0, cast_slice(indices));
+        let arena = self.pool.arena(device, pipeline.get_vertex_size() as u32);
+        let vertices = self.pool.arenas[arena].upload_vertices(device, queue, data);
+        let index_range = self.pool.arenas[arena].upload_indices(device, queue, indices);
 
         let mesh = IndexedMesh {
-            vertex_buffer,
-            index_buffer,
-            pipeline,
+            arena,
+            base_vertex: vertices.start,
+            vertex_count: vertices.len() as u32,
+            first_index: index_range.start,
             index_count: indices.len() as u32,
+            pipeline,
+            data: data.to_vec(),
+            indices: indices.to_vec(),
+            positions: self.keep_positions.then(|| mesh::positions(data, pipeline)),
+            bounds: Bounds::new(data, pipeline),
+            source: None,
         };
 
-        self.indexed_meshes.push(mesh);
+        self.indexed_meshes.push(Some(mesh));
         self.indexed_meshes.len() - 1
     }
+
+    /// Empties a mesh's slot, draws of it are skipped from then on. Frames
+    /// already handed to the renderer may still draw from its space, so it's
+    /// returned to be freed once they've finished.
+    pub fn unload(&mut self, mesh: MeshHandle) -> Option<MeshRanges> {
+        match mesh {
+            MeshHandle::Static(index) => {
+                let mesh = self.meshes.get_mut(index).and_then(Option::take)?;
+                Some(MeshRanges {
+                    arena: mesh.arena,
+                    vertices: mesh.vertices(),
+                    indices: None,
+                })
+            }
+            MeshHandle::Indexed(index) => {
+                let mesh = self.indexed_meshes.get_mut(index).and_then(Option::take)?;
+                Some(MeshRanges {
+                    arena: mesh.arena,
+                    vertices: mesh.vertices(),
+                    indices: Some(mesh.index_range()),
+                })
+            }
+        }
+    }
+
+    /// Reloads every mesh of a previous renderer, in order, so existing mesh ids stay valid.
+    pub fn restore(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        previous: &PreloadedRenderer,
+    ) {
+        for mesh in previous.meshes.iter() {
+            let Some(mesh) = mesh else {
+                self.meshes.push(None);
+                continue;
+            };
+            let index = self.load_static_mesh(device, queue, &mesh.data, mesh.pipeline);
+            self.meshes[index].as_mut().unwrap().source = mesh.source.clone();
+        }
+
+        for mesh in previous.indexed_meshes.iter() {
+            let Some(mesh) = mesh else {
+                self.indexed_meshes.push(None);
+                continue;
+            };
+            let index = self.load_static_mesh_indexed(
+                device,
+                queue,
+                &mesh.data,
+                &mesh.indices,
+                mesh.pipeline,
+            );
+            self.indexed_meshes[index].as_mut().unwrap().source = mesh.source.clone();
+        }
+    }
+
+    pub fn set_source(&mut self, mesh: MeshHandle, source: &str) {
+        let source = Some(source.to_string());
+        match mesh {
+            MeshHandle::Static(index) => {
+                if let Some(mesh) = &mut self.meshes[index] {
+                    mesh.source = source;
+                }
+            }
+            MeshHandle::Indexed(index) => {
+                if let Some(mesh) = &mut self.indexed_meshes[index] {
+                    mesh.source = source;
+                }
+            }
+        }
+    }
+
+    pub fn bounds(&self, mesh: MeshHandle) -> Option<Bounds> {
+        match mesh {
+            MeshHandle::Static(index) => self.meshes[index].as_ref().map(|mesh| mesh.bounds),
+            MeshHandle::Indexed(index) => {
+                self.indexed_meshes[index].as_ref().map(|mesh| mesh.bounds)
+            }
+        }
+    }
+
+    /// The pipeline a mesh is drawn with, None once it's unloaded.
+    pub fn pipeline(&self, mesh: MeshHandle) -> Option<Pipeline> {
+        match mesh {
+            MeshHandle::Static(index) => self.meshes[index].as_ref().map(|mesh| mesh.pipeline),
+            MeshHandle::Indexed(index) => self.indexed_meshes[index]
+                .as_ref()
+                .map(|mesh| mesh.pipeline),
+        }
+    }
 }
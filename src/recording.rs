@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::input::InputEvent;
+
+/// A single line of a recording, the events applied at the start of a frame.
+#[derive(Debug, Deserialize, Serialize)]
+struct FrameInput {
+    frame: u64,
+    events: Vec<InputEvent>,
+}
+
+/// Writes the input of each frame to a file, one json line per frame.
+/// Frames without any input are skipped, and the last frame is always
+/// written so playback knows how long the recording was.
+pub struct InputRecorder<W: Write = BufWriter<File>> {
+    writer: W,
+    last_frame: u64,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> Self {
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(e) => panic!("Failed to create recording {}: {e}", path.display()),
+        };
+
+        println!("Recording input to {}", path.display());
+
+        Self::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> InputRecorder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            last_frame: 0,
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, events: &[InputEvent]) {
+        self.last_frame = frame;
+        if !events.is_empty() {
+            self.write(frame, events);
+        }
+    }
+
+    /// Writes any events received after the last frame, ie the close request.
+    pub fn finish(&mut self, frame: u64, events: &[InputEvent]) {
+        if !events.is_empty() || frame > self.last_frame {
+            self.write(frame, events);
+        }
+        self.writer.flush().unwrap();
+    }
+
+    fn write(&mut self, frame: u64, events: &[InputEvent]) {
+        let line = FrameInput {
+            frame,
+            events: events.to_vec(),
+        };
+        serde_json::to_writer(&mut self.writer, &line).unwrap();
+        self.writer.write_all(b"\n").unwrap();
+    }
+}
+
+/// Feeds a recording back frame by frame. The frame index is the only clock,
+/// so the same recording always produces the same input.
+pub struct InputPlayback {
+    frames: Vec<FrameInput>,
+    next: usize,
+    length: u64,
+}
+
+impl InputPlayback {
+    pub fn load(path: &Path) -> Self {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => panic!("Failed to open recording {}: {e}", path.display()),
+        };
+
+        let playback = Self::read(BufReader::new(file), path);
+        println!(
+            "Playing back {} frames of input from {}",
+            playback.length,
+            path.display()
+        );
+        playback
+    }
+
+    /// Reads a recording, `path` is only used in errors.
+    pub fn read(reader: impl BufRead, path: &Path) -> Self {
+        let frames: Vec<FrameInput> = reader
+            .lines()
+            .map(|line| line.unwrap())
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(index, line)| match serde_json::from_str(&line) {
+                Ok(frame) => frame,
+                Err(e) => panic!(
+                    "Invalid recording {} line {}: {e}",
+                    path.display(),
+                    index + 1
+                ),
+            })
+            .collect();
+
+        let length = frames.last().map_or(0, |frame| frame.frame + 1);
+
+        Self {
+            frames,
+            next: 0,
+            length,
+        }
+    }
+
+    /// Returns the events for the given frame, frames must be requested in order.
+    pub fn events(&mut self, frame: u64) -> &[InputEvent] {
+        // Skip anything older, ie if a recording was edited out of order
+        while self
+            .frames
+            .get(self.next)
+            .is_some_and(|input| input.frame < frame)
+        {
+            self.next += 1;
+        }
+
+        match self.frames.get(self.next) {
+            Some(input) if input.frame == frame => {
+                self.next += 1;
+                &input.events
+            }
+            _ => &[],
+        }
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool {
+        frame >= self.length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+    use winit::{event::MouseButton, keyboard::KeyCode};
+
+    use crate::input::InputState;
+
+    use super::*;
+
+    // What a game can see of the input on a tick
+    fn snapshot(input: &InputState) -> (Vec<InputEvent>, bool, bool, bool, bool, Vec2) {
+        (
+            input.frame_events().to_vec(),
+            input.key_down(KeyCode::KeyW),
+            input.key_pressed(KeyCode::KeyW),
+            input.mouse_down(MouseButton::Left),
+            input.mouse_released(MouseButton::Left),
+            input.mouse_position(),
+        )
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let frames = [
+            vec![InputEvent::KeyDown(KeyCode::KeyW)],
+            vec![],
+            vec![
+                InputEvent::CursorMoved(20.0, 30.0),
+                InputEvent::MouseDown(MouseButton::Left),
+            ],
+            vec![
+                InputEvent::KeyUp(KeyCode::KeyW),
+                InputEvent::MouseUp(MouseButton::Left),
+            ],
+            vec![],
+            vec![],
+        ];
+
+        let mut recorder = InputRecorder::new(Vec::new());
+        let mut input = InputState::new();
+        let mut recorded = Vec::new();
+        for (frame, events) in frames.iter().enumerate() {
+            for event in events {
+                input.apply(*event);
+            }
+            recorder.record(frame as u64, input.frame_events());
+            recorded.push(snapshot(&input));
+            input.end_frame();
+        }
+        recorder.finish(frames.len() as u64, input.frame_events());
+
+        let mut playback = InputPlayback::read(recorder.writer.as_slice(), Path::new("test"));
+        let mut input = InputState::new();
+        for (frame, expected) in recorded.iter().enumerate() {
+            assert!(!playback.is_finished(frame as u64));
+            for event in playback.events(frame as u64) {
+                input.apply(*event);
+            }
+            assert_eq!(&snapshot(&input), expected, "frame {frame}");
+            input.end_frame();
+        }
+        assert!(playback.is_finished(frames.len() as u64 + 1));
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use crate::renderer::{FrameResult, FrameSubmission, Renderer};
+
+/// Renders submitted frames on their own thread, so the game can simulate and
+/// record the next frame meanwhile. At most `frames_in_flight` frames are
+/// submitted but not yet rendered, 1 renders every frame before returning.
+pub struct RenderThread {
+    frames: Option<Sender<FrameSubmission>>,
+    results: Receiver<FrameResult>,
+    handle: Option<JoinHandle<()>>,
+    in_flight: u32,
+    frames_in_flight: u32,
+}
+
+impl RenderThread {
+    pub fn spawn(mut renderer: Renderer, frames_in_flight: u32) -> Self {
+        let (frames, frame_receiver) = mpsc::channel::<FrameSubmission>();
+        let (result_sender, results) = mpsc::channel();
+
+        let handle = std::thread::Builder::new()
+            .name("render".to_string())
+            .spawn(move || {
+                for frame in frame_receiver {
+                    if result_sender.send(renderer.render(frame)).is_err() {
+                        break;
+                    }
+                }
+                renderer.device.poll(wgpu::Maintain::Wait);
+            })
+            .unwrap();
+
+        Self {
+            frames: Some(frames),
+            results,
+            handle: Some(handle),
+            in_flight: 0,
+            frames_in_flight,
+        }
+    }
+
+    /// Hands a frame to the render thread, waiting for an earlier one if too
+    /// many are in flight. Returns the results of frames finished since the last call.
+    pub fn submit(&mut self, frame: FrameSubmission) -> Vec<FrameResult> {
+        self.frames.as_ref().unwrap().send(frame).unwrap();
+        self.in_flight += 1;
+
+        let mut finished: Vec<FrameResult> = self.results.try_iter().collect();
+        self.in_flight -= finished.len() as u32;
+        while self.in_flight >= self.frames_in_flight {
+            finished.push(self.results.recv().unwrap());
+            self.in_flight -= 1;
+        }
+        finished
+    }
+
+    /// Waits for every frame in flight, ie before the surface is reconfigured.
+    pub fn wait(&mut self) -> Vec<FrameResult> {
+        let finished: Vec<FrameResult> = (0..self.in_flight)
+            .map(|_| self.results.recv().unwrap())
+            .collect();
+        self.in_flight = 0;
+        finished
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        // Closing the channel ends the thread once it's rendered what's left
+        self.frames = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,725 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use bytemuck::cast_slice;
+use glam::Mat4;
+use wgpu::RenderPipeline;
+
+use crate::{
+    camera::{CameraBuffers, CameraFrame},
+    config::AppConfig,
+    draw_sorting,
+    environment_map::EnvironmentMap,
+    frame_buffer::{FrameBuffer, FRAME_BUFFER_BIND_GROUP_INDEX, SCALING_BIND_GROUP_INDEX},
+    frame_stats::FrameStats,
+    gpu_culling::{CullCounts, GpuCulling},
+    gpu_timer::{GpuTimer, GpuTimings},
+    immediate_renderer::ImmediateRenderer,
+    indirect_draws::{IndirectDrawList, IndirectDraws, MAX_INDIRECT_DRAWS},
+    lights::{Lights, MAX_LIGHTS},
+    object_ids::{ObjectIds, OBJECT_ID_FORMAT},
+    pipeline::Pipeline,
+    preloaded_renderer::PreloadedRenderer,
+    quad_renderer::QuadRenderer,
+    resources::ResourceTotals,
+    textures::{self, Textures},
+    virtual_gpu::{INSTANCE_BUFFER_INDEX, OBJECT_ID_BUFFER_INDEX, PER_FRAME_BIND_GROUP_INDEX},
+    virtual_render_pass::VirtualRenderPass,
+};
+
+/// Where a frame is drawn to.
+pub enum RenderTarget {
+    /// Acquired by the renderer when the frame is rendered, and presented after.
+    Surface(Arc<wgpu::Surface<'static>>),
+    Texture(wgpu::TextureView),
+}
+
+/// A recorded frame, with everything needed to render it.
+pub struct FrameSubmission {
+    pub pass: VirtualRenderPass,
+    pub camera: CameraFrame,
+    pub environment: [f32; 4],
+    pub scaling: [f32; 2],
+    /// Frame buffer pixel to read the object id of.
+    pub pick: Option<(u32, u32)>,
+    pub target: RenderTarget,
+    /// How long the game took to record the pass, for the stats.
+    pub record_time: Duration,
+}
+
+/// What came of rendering a frame. The pass is handed back to be reused.
+pub struct FrameResult {
+    pub pass: VirtualRenderPass,
+    pub stats: FrameStats,
+    pub gpu_timings: Vec<GpuTimings>,
+    pub pick: Option<u32>,
+    /// The last counts read back from gpu culling.
+    pub culled: Option<CullCounts>,
+    /// The frame was skipped, the surface couldn't be acquired.
+    pub error: Option<wgpu::SurfaceError>,
+}
+
+/// Owns the gpu side of the virtual gpu, and turns recorded frames into wgpu
+/// passes. The game only shares textures and meshes with it, everything it
+/// writes to while rendering is its own.
+pub struct Renderer {
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<wgpu::Queue>,
+
+    pub render_pipelines: [RenderPipeline; 11],
+    /// Same vertex layouts as the render pipelines, drawing a flat color over everything.
+    pub highlight_pipelines: [RenderPipeline; 11],
+    pub textures: Arc<RwLock<Textures>>,
+    pub preloaded_renderer: Arc<RwLock<PreloadedRenderer>>,
+    pub quad_renderer: QuadRenderer,
+    pub immediate_renderer: ImmediateRenderer,
+
+    pub instance_buffer: wgpu::Buffer,
+    pub object_id_buffer: wgpu::Buffer,
+    pub lights: Lights,
+    pub camera_buffers: CameraBuffers,
+    pub environment_map: EnvironmentMap,
+    pub per_frame_bind_group: wgpu::BindGroup,
+
+    pub frame_buffer: FrameBuffer,
+    pub object_ids: Option<ObjectIds>,
+    pub gpu_timer: Option<GpuTimer>,
+    pub indirect_draws: Option<IndirectDraws>,
+    pub gpu_culling: Option<GpuCulling>,
+    /// Sorts static mesh draws by state before encoding them.
+    pub sort_draws: bool,
+}
+
+impl Renderer {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        config: &wgpu::SurfaceConfiguration,
+        app_config: &AppConfig,
+        textures: Arc<RwLock<Textures>>,
+        preloaded_renderer: Arc<RwLock<PreloadedRenderer>>,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Master Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+
+        let camera_buffers = CameraBuffers::new(&device);
+        let lights = Lights::new(&device);
+        let environment_map = EnvironmentMap::new(
+            &device,
+            &queue,
+            &app_config
+                .skybox
+                .clone()
+                .map(|path| app_config.asset_path(path)),
+        );
+
+        let per_frame_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Per Frame Bind Group Layout"),
+                entries: &[
+                    // Camera, fragment shaders need the projection for view directions
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Views
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Positions
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Projections
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Lights
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Env Map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let per_frame_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Per Frame Bind Group"),
+            layout: &per_frame_bind_group_layout,
+            entries: &[
+                // Camera Bindings
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffers.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: camera_buffers.views_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: camera_buffers.positions_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: camera_buffers.projections_buffer.as_entire_binding(),
+                },
+                // Lights Bindings
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: lights.buffer.as_entire_binding(),
+                },
+                // Env Map
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&environment_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&environment_map.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: environment_map.uniforms_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let texture_layouts = textures.read().unwrap();
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &per_frame_bind_group_layout,
+                    &texture_layouts.bind_group_layout,
+                    &texture_layouts.matcap_bind_group_layout, //Matcap Layout
+                ],
+                push_constant_ranges: &[],
+            });
+
+        drop(texture_layouts);
+
+        let indirect_draws = app_config
+            .indirect_draws
+            .then(|| IndirectDraws::new(&device))
+            .flatten();
+        let culling = app_config.gpu_culling && indirect_draws.is_some();
+
+        // 8mb of recorded instances, and room after them for those compacted by gpu culling
+        let instance_base = 8 * 1024 * 1024 / size_of::<Mat4>() as u32;
+        let instance_count = instance_base + culling as u32 * MAX_INDIRECT_DRAWS as u32;
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: instance_count as u64 * size_of::<Mat4>() as u64,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // One id per instance, so it's indexed like the model matrices
+        let object_id_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Object Id Buffer"),
+            size: instance_count as u64 * size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let gpu_culling = indirect_draws
+            .as_ref()
+            .filter(|_| culling)
+            .map(|indirect_draws| {
+                GpuCulling::new(
+                    &device,
+                    indirect_draws,
+                    &instance_buffer,
+                    &object_id_buffer,
+                    instance_base,
+                )
+            });
+        let object_ids = app_config
+            .object_id_picking
+            .then(|| ObjectIds::new(&device, config.width, config.height));
+
+        let frame_buffer = FrameBuffer::new(&device, config);
+        let gpu_timer = app_config
+            .gpu_timing
+            .then(|| GpuTimer::new(&device, &queue))
+            .flatten();
+
+        Self {
+            render_pipelines: generate_render_pipelines(
+                &device,
+                &shader,
+                &render_pipeline_layout,
+                config.format,
+                object_ids.is_some(),
+                false,
+            ),
+            highlight_pipelines: generate_render_pipelines(
+                &device,
+                &shader,
+                &render_pipeline_layout,
+                config.format,
+                object_ids.is_some(),
+                true,
+            ),
+            textures,
+            preloaded_renderer,
+            quad_renderer: QuadRenderer::new(&device, &queue),
+            immediate_renderer: ImmediateRenderer::new(&device),
+            instance_buffer,
+            object_id_buffer,
+            lights,
+            camera_buffers,
+            environment_map,
+            per_frame_bind_group,
+            frame_buffer,
+            object_ids,
+            gpu_timer,
+            indirect_draws,
+            gpu_culling,
+            sort_draws: app_config.sort_draws,
+            device,
+            queue,
+        }
+    }
+
+    /// Uploads the frame's data, encodes and submits its passes, then presents it.
+    pub fn render(&mut self, frame: FrameSubmission) -> FrameResult {
+        let FrameSubmission {
+            mut pass,
+            camera,
+            environment,
+            scaling,
+            pick,
+            target,
+            record_time,
+        } = frame;
+
+        let (surface_texture, surface_view) = match target {
+            RenderTarget::Surface(surface) => match surface.get_current_texture() {
+                Ok(output) => {
+                    let view = output
+                        .texture
+                        .create_view(&wgpu::TextureViewDescriptor::default());
+                    (Some(output), view)
+                }
+                Err(e) => {
+                    return FrameResult {
+                        pass,
+                        stats: FrameStats::default(),
+                        gpu_timings: Vec::new(),
+                        pick: None,
+                        culled: None,
+                        error: Some(e),
+                    }
+                }
+            },
+            RenderTarget::Texture(view) => (None, view),
+        };
+
+        let encode_start = Instant::now();
+        let mut stats = FrameStats {
+            record_time,
+            ..Default::default()
+        };
+
+        let gpu_timings = match &mut self.gpu_timer {
+            Some(gpu_timer) => gpu_timer.begin_frame(&self.device),
+            None => Vec::new(),
+        };
+        if let Some(object_ids) = &mut self.object_ids {
+            object_ids.poll(&self.device);
+            if let Some((x, y)) = pick {
+                object_ids.pick(x, y);
+            }
+        }
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.poll(&self.device);
+        }
+        if self.sort_draws {
+            let meshes = self.preloaded_renderer.read().unwrap();
+            stats.unsorted_switches = Some(draw_sorting::sort_draws(&mut pass, &meshes));
+        }
+
+        self.queue.write_buffer(
+            &self.immediate_renderer.buffer,
+            0,
+            cast_slice(&pass.immediate_data),
+        );
+        self.queue
+            .write_buffer(&self.instance_buffer, 0, cast_slice(&pass.matrices));
+        if self.object_ids.is_some() {
+            self.queue
+                .write_buffer(&self.object_id_buffer, 0, cast_slice(&pass.object_ids));
+        }
+        let lights = &pass.lights[..pass.lights.len().min(MAX_LIGHTS as usize)];
+        self.queue
+            .write_buffer(&self.lights.buffer, 0, cast_slice(lights));
+        self.camera_buffers.write(&self.queue, &camera);
+        self.queue.write_buffer(
+            &self.environment_map.uniforms_buffer,
+            0,
+            cast_slice(&environment),
+        );
+        self.queue
+            .write_buffer(&self.frame_buffer.scaling_buffer, 0, cast_slice(&scaling));
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Main Render Encoder"),
+            });
+
+        // Game Render Pass
+        {
+            let textures = self.textures.read().unwrap();
+            let meshes = self.preloaded_renderer.read().unwrap();
+            let indirect = match &self.indirect_draws {
+                Some(indirect_draws) => {
+                    let indirect = pass.indirect_draws(&meshes);
+                    indirect_draws.write(&self.queue, &indirect.args);
+                    indirect
+                }
+                None => IndirectDrawList::default(),
+            };
+            if let Some(gpu_culling) = &mut self.gpu_culling {
+                gpu_culling.cull(&self.queue, &mut encoder, &indirect, &camera.frustum());
+            }
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Main Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.frame_buffer.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 0.0,
+                                g: 0.0,
+                                b: 0.0,
+                                a: 1.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    self.object_ids
+                        .as_ref()
+                        .map(|object_ids| wgpu::RenderPassColorAttachment {
+                            view: &object_ids.view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                                store: wgpu::StoreOp::Store,
+                            },
+                        }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &textures.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(f32::NEG_INFINITY),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: self.gpu_timer.as_ref().and_then(GpuTimer::main_pass_writes),
+            });
+
+            render_pass.set_bind_group(PER_FRAME_BIND_GROUP_INDEX, &self.per_frame_bind_group, &[]);
+            render_pass.set_vertex_buffer(INSTANCE_BUFFER_INDEX, self.instance_buffer.slice(..));
+            render_pass.set_vertex_buffer(OBJECT_ID_BUFFER_INDEX, self.object_id_buffer.slice(..));
+
+            pass.execute(
+                &mut render_pass,
+                self,
+                &textures,
+                &meshes,
+                &indirect,
+                &mut stats,
+            );
+        }
+
+        if let Some(object_ids) = &mut self.object_ids {
+            object_ids.copy_requested(&mut encoder);
+        }
+
+        // Frame Buffer Render Pass
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Frame Buffer Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &surface_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 0.0,
+                            g: 0.0,
+                            b: 0.0,
+                            a: 1.0,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: self
+                    .gpu_timer
+                    .as_ref()
+                    .and_then(GpuTimer::frame_buffer_pass_writes),
+            });
+
+            render_pass.set_pipeline(&self.frame_buffer.pipeline);
+            render_pass.set_bind_group(
+                SCALING_BIND_GROUP_INDEX,
+                &self.frame_buffer.scaling_bind_group,
+                &[],
+            );
+            render_pass.set_bind_group(
+                FRAME_BUFFER_BIND_GROUP_INDEX,
+                &self.frame_buffer.texture_bind_group,
+                &[],
+            );
+            render_pass.draw(0..4, 0..1);
+        }
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.resolve(&mut encoder, &pass.markers);
+        }
+
+        let command_buffer = encoder.finish();
+        stats.encode_time = encode_start.elapsed();
+
+        let submit_start = Instant::now();
+        self.queue.submit(std::iter::once(command_buffer));
+        stats.submit_time = submit_start.elapsed();
+        if let Some(output) = surface_texture {
+            output.present();
+        }
+        if let Some(object_ids) = &mut self.object_ids {
+            object_ids.map_copied();
+        }
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.map_resolved();
+        }
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.map_copied();
+        }
+
+        stats.immediate_bytes = size_of_val(pass.immediate_data.as_slice()) as u64;
+        stats.instance_bytes = size_of_val(pass.matrices.as_slice()) as u64;
+        stats.tested_meshes = pass.tested_meshes;
+        stats.culled_meshes = pass.culled_meshes;
+        stats.light_bytes = size_of_val(lights) as u64;
+
+        FrameResult {
+            pick: self.object_ids.as_ref().and_then(ObjectIds::last_pick),
+            culled: self.gpu_culling.as_ref().and_then(GpuCulling::last_counts),
+            pass,
+            stats,
+            gpu_timings,
+            error: None,
+        }
+    }
+
+    /// Memory used by the renderer's own buffers and render targets. They're
+    /// all created with the renderer, and never resized.
+    pub fn buffer_totals(&self) -> ResourceTotals {
+        let (width, height) = self.frame_buffer.size();
+        // Color, depth and object ids are all 4 bytes a pixel
+        let attachments = if self.object_ids.is_some() { 3 } else { 2 };
+
+        ResourceTotals {
+            vertex_buffers: self.quad_renderer.quad_vertex_buffer.size(),
+            index_buffers: self.quad_renderer.quad_index_buffer.size(),
+            immediate_buffer: self.immediate_renderer.buffer.size(),
+            instance_buffer: self.instance_buffer.size(),
+            object_id_buffer: self.object_id_buffer.size(),
+            light_buffer: self.lights.buffer.size(),
+            uniform_buffers: self.camera_buffers.size()
+                + self.environment_map.uniforms_buffer.size()
+                + self.frame_buffer.scaling_buffer.size(),
+            indirect_buffers: self
+                .indirect_draws
+                .as_ref()
+                .map_or(0, |indirect_draws| indirect_draws.buffer.size()),
+            culling_buffers: self.gpu_culling.as_ref().map_or(0, GpuCulling::size),
+            timestamp_queries: self.gpu_timer.as_ref().map_or(0, GpuTimer::size),
+            readback_buffers: self.object_ids.as_ref().map_or(0, ObjectIds::readback_size),
+            render_targets: width as u64 * height as u64 * 4 * attachments,
+            ..Default::default()
+        }
+    }
+}
+
+fn generate_render_pipelines(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    object_ids: bool,
+    highlight: bool,
+) -> [RenderPipeline; 11] {
+    const PIPELINES: [Pipeline; 11] = [
+        Pipeline::Color,
+        Pipeline::Uv,
+        Pipeline::ColorUv,
+        Pipeline::ColorLit,
+        Pipeline::UvLit,
+        Pipeline::ColorUvLit,
+        Pipeline::Quad2d,
+        Pipeline::Matcap,
+        Pipeline::MatcapColor,
+        Pipeline::MatcapUv,
+        Pipeline::MatcapColorUv,
+    ];
+
+    std::array::from_fn(|i| {
+        let pipeline = PIPELINES[i];
+
+        create_render_pipeline(
+            device, shader, layout, format, object_ids, pipeline, highlight,
+        )
+    })
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    object_ids: bool,
+    pipeline: Pipeline,
+    highlight: bool,
+) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(pipeline.name()),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some(pipeline.vertex_shader()),
+            buffers: &pipeline.get_pipeline_buffers(),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(if highlight {
+                "fs_highlight"
+            } else {
+                pipeline.fragment_shader()
+            }),
+            targets: &[
+                Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(if highlight {
+                        wgpu::BlendState::ALPHA_BLENDING
+                    } else {
+                        wgpu::BlendState::REPLACE
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                // The shaders always write ids, they're dropped without the attachment
+                object_ids.then_some(wgpu::ColorTargetState {
+                    format: OBJECT_ID_FORMAT,
+                    blend: None,
+                    write_mask: if highlight {
+                        wgpu::ColorWrites::empty()
+                    } else {
+                        wgpu::ColorWrites::ALL
+                    },
+                }),
+            ],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // Highlights show back faces and hidden parts too
+            cull_mode: (!highlight).then_some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: textures::DepthTexture::DEPTH_FORMAT,
+            depth_write_enabled: !highlight,
+            depth_compare: if highlight {
+                wgpu::CompareFunction::Always
+            } else {
+                wgpu::CompareFunction::GreaterEqual
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
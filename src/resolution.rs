@@ -1,3 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
 pub enum Resolution {
     Full,   // 1920x1080
     High,   // 960x540
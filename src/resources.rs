@@ -0,0 +1,136 @@
+use std::fmt;
+
+use crate::{picking::MeshHandle, pipeline::Pipeline};
+
+#[derive(Clone)]
+pub struct TextureInfo {
+    /// None for the environment cubemap, which isn't bound by id.
+    pub id: Option<usize>,
+    pub width: u32,
+    pub height: u32,
+    /// 6 for the environment cubemap, 1 otherwise.
+    pub layers: u32,
+    pub format: wgpu::TextureFormat,
+    pub is_matcap: bool,
+    pub source: String,
+    pub bytes: u64,
+}
+
+pub struct MeshInfo {
+    pub mesh: MeshHandle,
+    pub pipeline: Pipeline,
+    pub vertex_count: u32,
+    /// None for meshes drawn without an index buffer.
+    pub index_count: Option<u32>,
+    /// Space taken up in the mesh pool, by the vertices and indices.
+    pub bytes: u64,
+    pub source: Option<String>,
+}
+
+/// Gpu memory in use, in bytes, by what it's used for.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceTotals {
+    pub textures: u64,
+    pub vertex_buffers: u64,
+    pub index_buffers: u64,
+    pub immediate_buffer: u64,
+    pub instance_buffer: u64,
+    pub object_id_buffer: u64,
+    pub light_buffer: u64,
+    pub uniform_buffers: u64,
+    /// Indirect draw args, when drawing indirectly.
+    pub indirect_buffers: u64,
+    /// The draws, batches, culled args and counts of gpu culling, with its uniforms and readback.
+    pub culling_buffers: u64,
+    /// The timestamp query set, and the buffers it's resolved and read back through.
+    pub timestamp_queries: u64,
+    /// Object ids read back for picking.
+    pub readback_buffers: u64,
+    /// The frame buffer, depth and object id attachments.
+    pub render_targets: u64,
+}
+
+impl ResourceTotals {
+    pub fn total(&self) -> u64 {
+        self.textures
+            + self.vertex_buffers
+            + self.index_buffers
+            + self.immediate_buffer
+            + self.instance_buffer
+            + self.object_id_buffer
+            + self.light_buffer
+            + self.uniform_buffers
+            + self.indirect_buffers
+            + self.culling_buffers
+            + self.timestamp_queries
+            + self.readback_buffers
+            + self.render_targets
+    }
+}
+
+/// Everything resident on the gpu, as returned by `VirtualGpu::resources`.
+pub struct ResourceReport {
+    pub textures: Vec<TextureInfo>,
+    pub meshes: Vec<MeshInfo>,
+    pub totals: ResourceTotals,
+}
+
+impl fmt::Display for ResourceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Textures:")?;
+        for texture in self.textures.iter() {
+            let id = match texture.id {
+                Some(id) => id.to_string(),
+                None => "env".to_string(),
+            };
+            let layers = match texture.layers {
+                1 => String::new(),
+                layers => format!("x{layers}"),
+            };
+            writeln!(
+                f,
+                "  {id:>3} {}x{}{layers} {:?}{} {} bytes, {}",
+                texture.width,
+                texture.height,
+                texture.format,
+                if texture.is_matcap { " matcap" } else { "" },
+                texture.bytes,
+                texture.source,
+            )?;
+        }
+
+        writeln!(f, "Meshes:")?;
+        for mesh in self.meshes.iter() {
+            let indices = match mesh.index_count {
+                Some(count) => format!(", {count} indices"),
+                None => String::new(),
+            };
+            writeln!(
+                f,
+                "  {:?} {} {} vertices{indices} {} bytes, {}",
+                mesh.mesh,
+                mesh.pipeline.name(),
+                mesh.vertex_count,
+                mesh.bytes,
+                mesh.source.as_deref().unwrap_or("unknown source"),
+            )?;
+        }
+
+        let totals = &self.totals;
+        writeln!(f, "Totals:")?;
+        writeln!(f, "  Textures: {} bytes", totals.textures)?;
+        writeln!(f, "  Vertex buffers: {} bytes", totals.vertex_buffers)?;
+        writeln!(f, "  Index buffers: {} bytes", totals.index_buffers)?;
+        writeln!(f, "  Immediate buffer: {} bytes", totals.immediate_buffer)?;
+        writeln!(f, "  Instance buffer: {} bytes", totals.instance_buffer)?;
+        writeln!(f, "  Object id buffer: {} bytes", totals.object_id_buffer)?;
+        writeln!(f, "  Light buffer: {} bytes", totals.light_buffer)?;
+        writeln!(f, "  Uniform buffers: {} bytes", totals.uniform_buffers)?;
+        writeln!(f, "  Indirect buffers: {} bytes", totals.indirect_buffers)?;
+        writeln!(f, "  Culling buffers: {} bytes", totals.culling_buffers)?;
+        writeln!(f, "  Timestamp queries: {} bytes", totals.timestamp_queries)?;
+        writeln!(f, "  Readback buffers: {} bytes", totals.readback_buffers)?;
+        writeln!(f, "  Render targets: {} bytes", totals.render_targets)?;
+        write!(f, "  Total: {} bytes", totals.total())
+    }
+}
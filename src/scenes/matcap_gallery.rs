@@ -0,0 +1,182 @@
+use std::fs;
+
+use glam::{Mat4, Vec3};
+use rayon::prelude::*;
+
+use crate::{
+    camera_controllers::OrbitController,
+    camera_path::{CameraPath, CameraPathPlayer},
+    command_list::CommandList,
+    contexts::{Draw3dContext, Init3dContext, Record3dContext},
+    game::Game,
+    importer,
+    input::{actions, InputState},
+    picking::MeshHandle,
+    pipeline::Pipeline,
+    time::GameTime,
+};
+
+// Animation speed, in units of t per second
+const SPEED: f32 = 1.0 / 3.0;
+
+pub struct MatcapGallery {
+    t: f32,
+    previous_t: f32,
+    camera: OrbitController,
+    camera_path: Option<CameraPathPlayer>,
+    show_camera_path: bool,
+
+    matcaps: Vec<usize>,
+    monkey_index: usize,
+    dog_matcap_mesh: usize,
+    dog_tex: usize,
+    dog_static: usize,
+
+    ship_tex: usize,
+    ship_mesh: usize,
+}
+
+impl MatcapGallery {
+    pub fn new() -> Self {
+        Self {
+            t: 0.0,
+            previous_t: 0.0,
+            camera: OrbitController::new(Vec3::new(0.0, 1.0, 0.0), 5.0),
+            camera_path: None,
+            show_camera_path: false,
+            matcaps: Vec::new(),
+            monkey_index: 0,
+            dog_matcap_mesh: 0,
+            dog_tex: 0,
+            dog_static: 0,
+            ship_tex: 0,
+            ship_mesh: 0,
+        }
+    }
+
+    fn draw_matcaps(&self, state: &mut dyn Draw3dContext, t: f32) {
+        let max = self.matcaps.len() as f32;
+        let offset = -(max / 2.0);
+        let distance = 2.5;
+        let rotation = Mat4::from_rotation_y(t * 0.5);
+
+        let scale = Mat4::from_scale(Vec3::splat(0.25));
+
+        // Each column is recorded on its own thread, and appended in order
+        let columns: Vec<CommandList> = self
+            .matcaps
+            .par_iter()
+            .enumerate()
+            .map(|(i, matcap_id)| {
+                let mut list = CommandList::new();
+                let translation = Vec3::new(offset + distance * i as f32, 0.0, 0.0);
+                list.push_matrix(Mat4::from_translation(translation) * rotation);
+                list.set_matcap(*matcap_id);
+                list.draw_static_mesh_indexed(self.monkey_index);
+
+                list.push_matrix(
+                    Mat4::from_translation(translation + Vec3::new(0.0, 2.0, 0.0)) * rotation,
+                );
+                list.set_texture(self.dog_tex);
+                list.draw_static_mesh_indexed(self.dog_matcap_mesh);
+
+                list.push_matrix(
+                    Mat4::from_translation(translation + Vec3::new(0.0, -2.0, 0.0))
+                        * rotation
+                        * scale,
+                );
+                list.set_texture(self.ship_tex);
+                list.draw_static_mesh_indexed(self.ship_mesh);
+                list
+            })
+            .collect();
+
+        for column in columns.iter() {
+            state.append_command_list(column);
+        }
+
+        state.set_texture(self.dog_tex);
+        state.push_matrix(Mat4::IDENTITY);
+        state.draw_static_mesh_indexed(self.dog_static);
+    }
+}
+
+impl Game for MatcapGallery {
+    fn name(&self) -> &str {
+        "Matcap Gallery"
+    }
+
+    fn init(&mut self, gpu: &mut dyn Init3dContext) {
+        let camera_path = CameraPath::load(gpu.asset_path("camera paths/gallery.toml"));
+        self.camera_path = Some(CameraPathPlayer::new(camera_path));
+
+        self.dog_tex = gpu.load_texture("dog tex.png", false);
+        self.ship_tex = gpu.load_texture("ship tex.png", false);
+
+        let (data, indices) =
+            importer::import_gltf(gpu.asset_path("dog.glb")).import_indexed(Pipeline::MatcapUv);
+        self.dog_matcap_mesh = gpu.load_static_mesh_indexed(&data, &indices, Pipeline::MatcapUv);
+        gpu.set_mesh_source(MeshHandle::Indexed(self.dog_matcap_mesh), "dog.glb");
+
+        let (data, indices) =
+            importer::import_gltf(gpu.asset_path("dog.glb")).import_indexed(Pipeline::Uv);
+        self.dog_static = gpu.load_static_mesh_indexed(&data, &indices, Pipeline::Uv);
+        gpu.set_mesh_source(MeshHandle::Indexed(self.dog_static), "dog.glb");
+
+        let (data, indices) =
+            importer::import_gltf(gpu.asset_path("ship.glb")).import_indexed(Pipeline::MatcapUv);
+        self.ship_mesh = gpu.load_static_mesh_indexed(&data, &indices, Pipeline::MatcapUv);
+        gpu.set_mesh_source(MeshHandle::Indexed(self.ship_mesh), "ship.glb");
+
+        for file in fs::read_dir(gpu.asset_path("matcaps")).unwrap() {
+            let file = file.unwrap();
+            println!("Loading matcap: {:?}", file.file_name());
+            let path = format!("matcaps/{}", file.file_name().to_str().unwrap());
+            let id = gpu.load_texture(&path, true);
+            self.matcaps.push(id);
+        }
+
+        let (monkey, monkey_indices) =
+            importer::import_gltf(gpu.asset_path("monkey1.glb")).import_indexed(Pipeline::Matcap);
+        self.monkey_index =
+            gpu.load_static_mesh_indexed(&monkey, &monkey_indices, Pipeline::Matcap);
+        gpu.set_mesh_source(MeshHandle::Indexed(self.monkey_index), "monkey1.glb");
+    }
+
+    fn update(&mut self, input: &InputState, time: &GameTime) {
+        self.camera.update(input, time);
+
+        let camera_path = self.camera_path.as_mut().unwrap();
+        if input.action_pressed(actions::PLAY_CAMERA_PATH) {
+            if camera_path.playing {
+                camera_path.playing = false;
+            } else {
+                camera_path.play();
+            }
+        }
+        if input.action_pressed(actions::SHOW_CAMERA_PATH) {
+            self.show_camera_path = !self.show_camera_path;
+        }
+        camera_path.update(time);
+
+        self.previous_t = self.t;
+        self.t += time.dt * SPEED;
+    }
+
+    fn draw(&self, state: &mut dyn Draw3dContext, alpha: f32) {
+        let camera_path = self.camera_path.as_ref().unwrap();
+        if camera_path.playing {
+            camera_path.apply(state, alpha);
+        } else {
+            state.set_fov(45.0);
+            self.camera.apply(state, alpha);
+        }
+
+        let t = self.previous_t + (self.t - self.previous_t) * alpha;
+        self.draw_matcaps(state, t);
+
+        if self.show_camera_path {
+            camera_path.path.draw_preview(state);
+        }
+    }
+}
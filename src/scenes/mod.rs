@@ -0,0 +1,5 @@
+mod matcap_gallery;
+mod pbr_test;
+
+pub use matcap_gallery::MatcapGallery;
+pub use pbr_test::PbrTest;
@@ -0,0 +1,253 @@
+use std::cell::Cell;
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use winit::event::MouseButton;
+
+use crate::{
+    camera_controllers::FreeFlyController,
+    contexts::{Draw3dContext, Init3dContext},
+    game::Game,
+    importer,
+    input::InputState,
+    lights::Light,
+    picking::{MeshHandle, RayHit},
+    pipeline::Pipeline,
+    time::GameTime,
+};
+
+// Animation speed, in units of t per second
+const SPEED: f32 = 1.0 / 3.0;
+// Lifts the picked triangle's highlight off the mesh, towards the camera
+const HIGHLIGHT_OFFSET: f32 = 0.01;
+
+pub struct PbrTest {
+    t: f32,
+    previous_t: f32,
+    camera: FreeFlyController,
+    fox_tex: usize,
+
+    immediate_cube: Vec<f32>,
+    immediate_fox: Vec<f32>,
+
+    cube_static_indexed: usize,
+    fox_static_raw: usize,
+    test_sphere: usize,
+    tex_grid: usize,
+
+    pbr_test: usize,
+
+    // Set by a click, and picked when the next frame is drawn
+    click: Cell<Option<Vec2>>,
+    picked: Cell<Option<RayHit>>,
+}
+
+impl PbrTest {
+    pub fn new() -> Self {
+        Self {
+            t: 0.0,
+            previous_t: 0.0,
+            camera: FreeFlyController::new(Vec3::new(0.0, 1.0, 5.0), 0.0, 0.0),
+            immediate_cube: Vec::new(),
+            immediate_fox: Vec::new(),
+            fox_tex: 0,
+            cube_static_indexed: 0,
+            fox_static_raw: 0,
+            test_sphere: 0,
+            tex_grid: 0,
+            pbr_test: 0,
+            click: Cell::new(None),
+            picked: Cell::new(None),
+        }
+    }
+
+    fn draw_pbr_test(&self, state: &mut dyn Draw3dContext, t: f32) {
+        state.push_matrix(Mat4::IDENTITY);
+        state.draw_static_mesh(self.pbr_test);
+
+        // state.push_matrix(Mat4::from_translation(Vec3::new(0.0, 1.0, -2.0)));
+        // state.draw_static_mesh_indexed(self.test_sphere);
+
+        // state.draw_tri_list(&self.immediate_cube, Pipeline::Color);
+
+        // state.push_matrix(
+        //     Mat4::from_translation(Vec3::new(50.0, 50.0, 1.0))
+        //         * Mat4::from_scale(Vec3::splat(128.0)),
+        // );
+        // state.draw_sprite(self.tex_grid);
+
+        // state.push_matrix(
+        //     Mat4::from_translation(Vec3::new(100.0, 150.0, 0.999))
+        //         * Mat4::from_scale(Vec3::splat(256.0)),
+        // );
+        // // state.draw_sprite(self.tex_index);
+        // state.set_texture(self.tex_index);
+        // state.push_matrix(Mat4::from_scale(Vec3::splat(0.025)));
+        // state.draw_tri_list(&self.immediate_fox, Pipeline::Uv);
+
+        // let cube_transform =
+        //     Mat4::from_translation(Vec3::new(-3.0, 0.0, 0.0)) * Mat4::from_rotation_y(t);
+        // state.push_matrix(cube_transform);
+        // state.draw_static_mesh_indexed(self.cube_static_indexed);
+
+        // let fox_transform = Mat4::from_translation(Vec3::new(3.0, 3.0, 0.0))
+        //     * Mat4::from_rotation_y(t)
+        //     * Mat4::from_scale(Vec3::splat(0.025));
+        // state.push_matrix(fox_transform);
+        // state.draw_static_mesh(self.fox_static_raw);
+
+        // Ambient Light
+        // state.push_light(&Light {
+        //     color_intensity: Vec4::new(1.0, 1.0, 1.0, 0.05),
+        //     position_range: Vec4::splat(-1.0),
+        //     direction_angle: Vec4::ZERO,
+        // });
+
+        // Point Lights
+        for n in 0..2 {
+            let color_max_angle = if n == 0 {
+                Vec4::new(1.0, 0.0, 0.0, 1.0)
+            } else {
+                Vec4::new(0.0, 1.0, 0.0, 1.0)
+            };
+            //let light_x = t.sin() * 2.0;
+            let light_y = t.cos() * 2.0 * n as f32;
+            let light_z = 1.0;
+            let light_offset = Vec4::new((25.0 / 2.0) * n as f32, light_y, light_z, 1.0);
+            let modified_position = Mat4::from_rotation_y(t + (n as f32 * 15.0)) * light_offset;
+            state.push_light(&Light {
+                color_max_angle,
+                position_range: modified_position.xyz().extend((t.sin() * 0.5 + 0.5) * 50.0),
+                direction_min_angle: Vec4::ZERO,
+            });
+        }
+
+        let camera_pos = state.get_camera().eye();
+        let forward = state.get_camera().get_forward();
+
+        // Spot Light
+        state.push_light(&Light {
+            color_max_angle: Vec4::new(1.0, 1.0, 1.0, 15.0_f32.to_radians().cos()),
+            position_range: camera_pos.extend(15.0),
+            direction_min_angle: forward.extend(12.5_f32.to_radians().cos()),
+        });
+
+        // Directional Light, Pointing Left, Down, Forward
+        state.push_light(&Light {
+            color_max_angle: Vec4::splat(1.0),
+            position_range: Vec4::ZERO,
+            direction_min_angle: Vec4::new(-1.0, -1.0, -1.0, 0.0),
+        });
+    }
+
+    /// Picks the mesh under the last click, and highlights the triangle hit.
+    fn draw_picked(&self, state: &mut dyn Draw3dContext) {
+        if let Some(position) = self.click.take() {
+            let hit = state
+                .get_camera()
+                .screen_to_ray(position.x, position.y)
+                .and_then(|ray| state.raycast(&ray));
+            if let Some(hit) = &hit {
+                println!(
+                    "Picked {:?} instance {} triangle {} at {}",
+                    hit.mesh, hit.instance, hit.triangle_index, hit.point
+                );
+            }
+            self.picked.set(hit);
+        }
+
+        let Some(hit) = self.picked.get() else {
+            return;
+        };
+        let eye = Vec3::from(state.get_camera().eye());
+        let mut data = Vec::new();
+        for corner in hit.triangle {
+            let corner = corner + (eye - corner).normalize_or_zero() * HIGHLIGHT_OFFSET;
+            data.extend_from_slice(&corner.to_array());
+            data.extend_from_slice(&[1.0, 1.0, 0.0]);
+        }
+        state.push_matrix(Mat4::IDENTITY);
+        state.draw_tri_list(&data, Pipeline::Color);
+    }
+}
+
+impl Game for PbrTest {
+    fn name(&self) -> &str {
+        "PBR Test"
+    }
+
+    fn init(&mut self, gpu: &mut dyn Init3dContext) {
+        self.immediate_cube = importer::import_gltf(gpu.asset_path("BoxVertexColors.glb"))
+            .import_indexed_to_non_indexed();
+        self.immediate_fox = importer::import_gltf(gpu.asset_path("Fox.glb")).import(Pipeline::Uv);
+
+        self.fox_tex = gpu.load_texture("Fox.png", false);
+        self.tex_grid = gpu.load_texture("color grid 128x128.png", false);
+
+        let (vertices, indices) = importer::import_gltf(gpu.asset_path("BoxVertexColors.glb"))
+            .import_indexed(Pipeline::Color);
+
+        self.cube_static_indexed =
+            gpu.load_static_mesh_indexed(&vertices, &indices, Pipeline::Color);
+        gpu.set_mesh_source(
+            MeshHandle::Indexed(self.cube_static_indexed),
+            "BoxVertexColors.glb",
+        );
+
+        let (vertices, indices) = importer::import_gltf(gpu.asset_path("test sphere metallic.glb"))
+            .import_indexed(Pipeline::ColorLit);
+        self.test_sphere = gpu.load_static_mesh_indexed(&vertices, &indices, Pipeline::ColorLit);
+        gpu.set_mesh_source(
+            MeshHandle::Indexed(self.test_sphere),
+            "test sphere metallic.glb",
+        );
+
+        let data = importer::import_gltf(gpu.asset_path("Fox.glb")).import(Pipeline::Uv);
+        self.fox_static_raw = gpu.load_static_mesh(&data, Pipeline::Uv);
+        gpu.set_mesh_source(MeshHandle::Static(self.fox_static_raw), "Fox.glb");
+
+        let (sphere, sphere_indices) =
+            importer::import_gltf(gpu.asset_path("test sphere base.glb"))
+                .import_indexed(Pipeline::ColorLit);
+        let mut spheres = Vec::new();
+
+        for metallic in 0..5 {
+            for roughness in 0..11 {
+                for index in sphere_indices.iter() {
+                    let start = *index as usize * 12;
+                    let end = start + 12;
+                    let vertex = &sphere[start..end];
+                    let x = vertex[0] + roughness as f32 * 2.0;
+                    let y = vertex[1] + metallic as f32 * 2.0 - (3.0);
+                    let z = vertex[2];
+                    let to_copy = &vertex[3..9];
+                    let lighting = &[metallic as f32 / 4.0, roughness as f32 / 10.0, 0.0];
+                    spheres.extend_from_slice(&[x, y, z]);
+                    spheres.extend_from_slice(to_copy); // Color, Normals
+                    spheres.extend_from_slice(lighting);
+                }
+            }
+        }
+
+        self.pbr_test = gpu.load_static_mesh(&spheres, Pipeline::ColorLit);
+        gpu.set_mesh_source(
+            MeshHandle::Static(self.pbr_test),
+            "test sphere base.glb, as a grid",
+        );
+    }
+
+    fn update(&mut self, input: &InputState, time: &GameTime) {
+        self.camera.update(input, time);
+        if input.mouse_pressed(MouseButton::Left) {
+            self.click.set(Some(input.mouse_position()));
+        }
+        self.previous_t = self.t;
+        self.t += time.dt * SPEED;
+    }
+
+    fn draw(&self, state: &mut dyn Draw3dContext, alpha: f32) {
+        self.camera.apply(state, alpha);
+        let t = self.previous_t + (self.t - self.previous_t) * alpha;
+        self.draw_pbr_test(state, t);
+        self.draw_picked(state);
+    }
+}
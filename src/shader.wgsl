@@ -17,6 +17,29 @@ struct Camera {
     pos: vec4<f32>,
 }
 
+// Orthographic projections have no perspective divide, so the direction
+// towards the eye is the same everywhere on screen
+fn is_orthographic() -> bool {
+    return camera.proj[3][3] == 1.0;
+}
+
+// Direction from a view space position towards the eye
+fn view_direction(view_position: vec3<f32>) -> vec3<f32> {
+    if is_orthographic() {
+        return vec3<f32>(0.0, 0.0, 1.0);
+    }
+    return normalize(-view_position);
+}
+
+// Direction from a world space position towards the eye
+fn eye_direction(world_position: vec4<f32>) -> vec4<f32> {
+    if is_orthographic() {
+        // The view's Z row is the camera's backward axis in world space
+        return vec4<f32>(camera.view[0][2], camera.view[1][2], camera.view[2][2], 0.0);
+    }
+    return normalize(camera.pos - world_position);
+}
+
 @group(0) @binding(1)
 var<storage> views: array<mat4x4<f32>>;
 
@@ -63,6 +86,13 @@ struct InstanceInput {
     @location(7) model_matrix_1: vec4<f32>,
     @location(8) model_matrix_2: vec4<f32>,
     @location(9) model_matrix_3: vec4<f32>,
+    @location(10) object_id: u32,
+}
+
+// Fragments also write the object id, for picking when the id attachment is enabled
+struct FragmentOut {
+    @location(0) color: vec4<f32>,
+    @location(1) object_id: u32,
 }
 
 // Vertex Inputs
@@ -94,6 +124,7 @@ struct VertexColorIn {
 struct VertexColorOut {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -110,12 +141,19 @@ fn vs_color(
     );
     out.color = model.color;
     out.clip_position = camera.proj * camera.view * model_matrix * vec4<f32>(model.position, 1.0);
+    out.object_id = instance.object_id;
     return out;
 }
 
+// Flat translucent color over the draw selected in the frame debugger
+@fragment
+fn fs_highlight() -> FragmentOut {
+    return FragmentOut(vec4<f32>(1.0, 0.0, 1.0, 0.5), 0u);
+}
+
 @fragment
-fn fs_color(in: VertexColorOut) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+fn fs_color(in: VertexColorOut) -> FragmentOut {
+    return FragmentOut(vec4<f32>(in.color, 1.0), in.object_id);
 }
 
 // Vertex UVs
@@ -127,6 +165,7 @@ struct VertexUvIn {
 struct VertexUvOut {
     @builtin(position) clip_position: vec4<f32>,
     @location(1) uvs: vec2<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -143,12 +182,13 @@ fn vs_uv(
     );
     out.uvs = model.uvs;
     out.clip_position = camera.proj * camera.view * model_matrix * vec4<f32>(model.position, 1.0);
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_uv(in: VertexUvOut) -> @location(0) vec4<f32> {
-    return textureSample(t_albedo, s_albedo, in.uvs);
+fn fs_uv(in: VertexUvOut) -> FragmentOut {
+    return FragmentOut(textureSample(t_albedo, s_albedo, in.uvs), in.object_id);
 }
 
 // Vertex Color + UVs
@@ -162,6 +202,7 @@ struct VertexColorUvOut {
     @builtin(position) clip_position: vec4<f32>,
     @location(1) color: vec3<f32>,
     @location(2) uvs: vec2<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -179,13 +220,14 @@ fn vs_color_uv(
     out.color = model.color;
     out.uvs = model.uvs;
     out.clip_position = camera.proj * camera.view * model_matrix * vec4<f32>(model.position, 1.0);
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_color_uv(in: VertexColorUvOut) -> @location(0) vec4<f32> {
+fn fs_color_uv(in: VertexColorUvOut) -> FragmentOut {
     let texel = textureSample(t_albedo, s_albedo, in.uvs).rgb;
-    return vec4<f32>(in.color * texel, 1.0);
+    return FragmentOut(vec4<f32>(in.color * texel, 1.0), in.object_id);
 }
 
 // Vertex Color + Lighting
@@ -203,6 +245,7 @@ struct VertexColorLitOut {
     @location(4) lighting: vec3<f32>, // Metallic, Roughness, Emissive
     @location(0) view_pos: vec3<f32>,
     @location(5) world_reflection: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -218,24 +261,25 @@ fn vs_color_lit(
         instance.model_matrix_3,
     );
 
-    let view_position = views[0] * model_matrix * vec4<f32>(model.position, 1.0);
+    let view_position = camera.view * model_matrix * vec4<f32>(model.position, 1.0);
 
-    out.clip_position = projections[0] * view_position;
+    out.clip_position = camera.proj * view_position;
     out.color = model.color;
-    out.normals = normalize((views[0] * model_matrix * vec4<f32>(model.normals, 0.0)).xyz);
+    out.normals = normalize((camera.view * model_matrix * vec4<f32>(model.normals, 0.0)).xyz);
     out.view_pos = view_position.xyz;
     out.lighting = model.lighting;
 
     let world_position = model_matrix * vec4<f32>(model.position, 1.0);
     let world_normal = normalize(model_matrix * vec4<f32>(model.normals, 0.0));
-    let incoming = normalize(positions[0] - world_position);
+    let incoming = eye_direction(world_position);
     out.world_reflection = reflect(incoming, world_normal).xyz;
 
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_color_lit(in: VertexColorLitOut) -> @location(0) vec4<f32> {
+fn fs_color_lit(in: VertexColorLitOut) -> FragmentOut {
     let frag_color = in.color;
 
     let output_color = calculate_lighting(
@@ -245,7 +289,7 @@ fn fs_color_lit(in: VertexColorLitOut) -> @location(0) vec4<f32> {
         in.world_reflection,
         in.lighting
     );
-    return vec4<f32>(output_color, 1.0);
+    return FragmentOut(vec4<f32>(output_color, 1.0), in.object_id);
 }
 
 // Vertex UV + Lighting
@@ -263,6 +307,7 @@ struct VertexUvLitOut {
     @location(4) lighting: vec3<f32>, // Metallic, Roughness, Emissive
     @location(0) view_pos: vec3<f32>,
     @location(5) world_reflection: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -288,14 +333,15 @@ fn vs_uv_lit(
 
     let world_position = model_matrix * vec4<f32>(model.position, 1.0);
     let world_normal = normalize(model_matrix * vec4<f32>(model.normals, 0.0));
-    let incoming = normalize(camera.pos - world_position);
+    let incoming = eye_direction(world_position);
     out.world_reflection = reflect(incoming, world_normal).xyz;
 
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_uv_lit(in: VertexUvLitOut) -> @location(0) vec4<f32> {
+fn fs_uv_lit(in: VertexUvLitOut) -> FragmentOut {
     let frag_color = textureSample(t_albedo, s_albedo, in.uvs).rgb;
 
     let output_color = calculate_lighting(
@@ -305,7 +351,7 @@ fn fs_uv_lit(in: VertexUvLitOut) -> @location(0) vec4<f32> {
         in.world_reflection,
         in.lighting
     );
-    return vec4<f32>(output_color, 1.0);
+    return FragmentOut(vec4<f32>(output_color, 1.0), in.object_id);
 }
 
 // Vertex Color + UV + Lighting
@@ -325,6 +371,7 @@ struct VertexColorUvLitOut {
     @location(4) lighting: vec3<f32>, // Metallic, Roughness, Emissive
     @location(0) view_pos: vec3<f32>,
     @location(5) world_reflection: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -351,14 +398,15 @@ fn vs_color_uv_lit(
 
     let world_position = model_matrix * vec4<f32>(model.position, 1.0);
     let world_normal = normalize(model_matrix * vec4<f32>(model.normals, 0.0));
-    let incoming = normalize(camera.pos - world_position);
+    let incoming = eye_direction(world_position);
     out.world_reflection = reflect(incoming, world_normal).xyz;
 
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_color_uv_lit(in: VertexColorUvLitOut) -> @location(0) vec4<f32> {
+fn fs_color_uv_lit(in: VertexColorUvLitOut) -> FragmentOut {
     let texel = textureSample(t_albedo, s_albedo, in.uvs).rgb;
     let frag_color = in.color * texel.rgb;
 
@@ -369,7 +417,7 @@ fn fs_color_uv_lit(in: VertexColorUvLitOut) -> @location(0) vec4<f32> {
         in.world_reflection,
         in.lighting
     );
-    return vec4<f32>(output_color, 1.0);
+    return FragmentOut(vec4<f32>(output_color, 1.0), in.object_id);
 }
 
 // Lighting Parts
@@ -404,7 +452,7 @@ fn calculate_light(
     var light_dir: vec3<f32>;
 
     // View direction in view space
-    let view_dir = normalize(-view_position);
+    let view_dir = view_direction(view_position);
     let n_dot_v = max(dot(view_normal, view_dir), 0.0);
 
     var attenuation = 1.0;
@@ -576,7 +624,7 @@ fn calculate_lighting(
     reflection.y = -reflection.y;
     let reflection_color = get_reflection(reflection, roughness);
 
-    let n_dot_v = dot(n_normal, normalize(-view_pos));
+    let n_dot_v = dot(n_normal, view_direction(view_pos));
 
     // Apply environment color
     output_color += tri_ace_environment(albedo, reflection_color, metallic, n_dot_v);
@@ -607,6 +655,7 @@ fn vs_quad_2d(
     out.uvs = model.uvs;
     out.clip_position = camera.ortho * model_matrix * vec4<f32>(model.position, 1.0);
 
+    out.object_id = instance.object_id;
     return out;
 }
 
@@ -629,6 +678,7 @@ struct VertexMatcapOut {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) view_pos: vec3<f32>,
     @location(3) normals: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 }
 
 @vertex
@@ -650,16 +700,17 @@ fn vs_matcap(
     out.view_pos = view_position.xyz;
     out.normals = normalize((camera.view * model_matrix * vec4<f32>(model.normals, 0.0)).xyz);
 
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_matcap(in: VertexMatcapOut) -> @location(0) vec4<f32> {
+fn fs_matcap(in: VertexMatcapOut) -> FragmentOut {
     let normal = normalize(in.normals);
-    let view = normalize(-in.view_pos);
+    let view = view_direction(in.view_pos);
     let uv = matcap_uv(view, normal);
     let matcap_texel = textureSample(t_matcap, s_matcap, uv).rgb;
-    return vec4<f32>(matcap_texel, 1.0);
+    return FragmentOut(vec4<f32>(matcap_texel, 1.0), in.object_id);
 }
 
 struct VertexMatcapColorIn {
@@ -673,6 +724,7 @@ struct VertexMatcapColorOut {
     @location(1) color: vec3<f32>,
     @location(3) normals: vec3<f32>,
     @location(0) view_pos: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -695,16 +747,17 @@ fn vs_matcap_color(
     out.normals = normalize((camera.view * model_matrix * vec4<f32>(model.normals, 0.0)).xyz);
     out.color = model.color;
 
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_matcap_color(in: VertexMatcapColorOut) -> @location(0) vec4<f32> {
+fn fs_matcap_color(in: VertexMatcapColorOut) -> FragmentOut {
     let normal = normalize(in.normals);
-    let view = normalize(-in.view_pos);
+    let view = view_direction(in.view_pos);
     let matcap_uv = matcap_uv(view, normal);
     let matcap_texel = textureSample(t_matcap, s_matcap, matcap_uv).rgb;
-    return vec4<f32>(matcap_texel * in.color, 1.0);
+    return FragmentOut(vec4<f32>(matcap_texel * in.color, 1.0), in.object_id);
 }
 
 struct VertexMatcapUvIn {
@@ -718,6 +771,7 @@ struct VertexMatcapUvOut {
     @location(2) uvs: vec2<f32>,
     @location(3) normals: vec3<f32>,
     @location(0) view_pos: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -740,17 +794,18 @@ fn vs_matcap_uv(
     out.normals = normalize((camera.view * model_matrix * vec4<f32>(model.normals, 0.0)).xyz);
     out.uvs = model.uvs;
 
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_matcap_uv(in: VertexMatcapUvOut) -> @location(0) vec4<f32> {
+fn fs_matcap_uv(in: VertexMatcapUvOut) -> FragmentOut {
     let normal = normalize(in.normals);
-    let view = normalize(-in.view_pos);
+    let view = view_direction(in.view_pos);
     let matcap_uv = matcap_uv(view, normal);
     let matcap_texel = textureSample(t_matcap, s_matcap, matcap_uv).rgb;
     let texel = textureSample(t_albedo, s_albedo, in.uvs).rgb;
-    return vec4<f32>(matcap_texel * texel, 1.0);
+    return FragmentOut(vec4<f32>(matcap_texel * texel, 1.0), in.object_id);
 }
 
 struct VertexMatcapColorUvIn {
@@ -766,6 +821,7 @@ struct VertexMatcapColorUvOut {
     @location(2) uvs: vec2<f32>,
     @location(3) normals: vec3<f32>,
     @location(0) view_pos: vec3<f32>,
+    @location(10) @interpolate(flat) object_id: u32,
 };
 
 @vertex
@@ -789,16 +845,17 @@ fn vs_matcap_color_uv(
     out.uvs = model.uvs;
     out.color = model.color;
 
+    out.object_id = instance.object_id;
     return out;
 }
 
 @fragment
-fn fs_matcap_color_uv(in: VertexMatcapColorUvOut) -> @location(0) vec4<f32> {
+fn fs_matcap_color_uv(in: VertexMatcapColorUvOut) -> FragmentOut {
     let normal = normalize(in.normals);
-    let view = normalize(-in.view_pos);
+    let view = view_direction(in.view_pos);
     let matcap_uv = matcap_uv(view, normal);
     let matcap_texel = textureSample(t_matcap, s_matcap, matcap_uv).rgb;
     let texel = textureSample(t_albedo, s_albedo, in.uvs).rgb;
 
-    return vec4<f32>(matcap_texel * texel * in.color, 1.0);
+    return FragmentOut(vec4<f32>(matcap_texel * texel * in.color, 1.0), in.object_id);
 }
\ No newline at end of file
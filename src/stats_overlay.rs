@@ -0,0 +1,120 @@
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+
+use crate::textures::Textures;
+
+// Glyphs are 3x5 pixels, with a pixel of spacing to the right and below
+const GLYPH_WIDTH: u32 = 4;
+const GLYPH_HEIGHT: u32 = 6;
+const PIXEL_SCALE: f32 = 2.0;
+const MARGIN: f32 = 8.0;
+// In front of anything drawn in 3d, with reverse-Z
+const DEPTH: f32 = 0.99;
+
+const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+const BACKGROUND_COLOR: [u8; 4] = [20, 20, 20, 255];
+
+// Each row is 3 bits, the most significant bit is the left pixel
+const FONT: &[(char, [u8; 5])] = &[
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('?', [0b111, 0b001, 0b010, 0b000, 0b010]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+];
+
+/// Draws lines of text as sprites, one texture per glyph. Only upper case
+/// letters, digits and a little punctuation are supported.
+pub struct StatsOverlay {
+    glyphs: Vec<(char, usize)>,
+}
+
+impl StatsOverlay {
+    /// Generates the glyph textures. They're made before any game textures,
+    /// so texture ids stay the same when the device is rebuilt.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, textures: &mut Textures) -> Self {
+        let glyphs = FONT
+            .iter()
+            .map(|(character, rows)| {
+                let image = RgbaImage::from_fn(GLYPH_WIDTH, GLYPH_HEIGHT, |x, y| {
+                    let lit = y < 5 && x < 3 && rows[y as usize] & (0b100 >> x) != 0;
+                    image::Rgba(if lit { TEXT_COLOR } else { BACKGROUND_COLOR })
+                });
+                let source = format!("generated glyph '{character}'");
+                let id = textures.load_texture_rgba(device, queue, &image, &source, false);
+                (*character, id)
+            })
+            .collect();
+
+        Self { glyphs }
+    }
+
+    fn glyph(&self, character: char) -> usize {
+        let find = |character: char| self.glyphs.iter().find(|(c, _)| *c == character);
+        find(character.to_ascii_uppercase())
+            .or_else(|| find('?'))
+            .unwrap()
+            .1
+    }
+
+    /// Lays the lines out in the top left corner of the frame, as sprite
+    /// matrices and glyph textures.
+    pub fn layout(&self, lines: &[String]) -> Vec<(Mat4, usize)> {
+        let mut sprites = Vec::new();
+        let width = GLYPH_WIDTH as f32 * PIXEL_SCALE;
+        let height = GLYPH_HEIGHT as f32 * PIXEL_SCALE;
+        let scale = Mat4::from_scale(Vec3::new(width, height, 1.0));
+
+        for (row, line) in lines.iter().enumerate() {
+            for (column, character) in line.chars().enumerate() {
+                let x = MARGIN + column as f32 * width + width / 2.0;
+                let y = MARGIN + row as f32 * height + height / 2.0;
+                let matrix = Mat4::from_translation(Vec3::new(x, y, DEPTH)) * scale;
+                sprites.push((matrix, self.glyph(character)));
+            }
+        }
+        sprites
+    }
+}
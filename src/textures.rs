@@ -1,4 +1,11 @@
-use image::ImageReader;
+use image::{ImageReader, RgbaImage};
+
+// Loaded by the virtual gpu before any game textures
+pub const DEFAULT_TEXTURE: usize = 0;
+pub const DEFAULT_MATCAP: usize = 1;
+
+// Every loaded texture is converted to this
+const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 
 pub struct Textures {
     pub bind_group_layout: wgpu::BindGroupLayout,
@@ -88,6 +95,19 @@ impl Textures {
             Some(image) => image,
             None => &image.to_rgba8(),
         };
+        self.load_texture_rgba(device, queue, image, path, is_matcap)
+    }
+
+    /// Uploads an image already in memory. `source` is only used to reload it,
+    /// so generated textures must be made before any loaded ones.
+    pub fn load_texture_rgba(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &RgbaImage,
+        source: &str,
+        is_matcap: bool,
+    ) -> usize {
         let dimensions = image.dimensions();
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -100,7 +120,7 @@ impl Textures {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: TEXTURE_FORMAT,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             label: Some("diffuse_texture"),
             view_formats: &[],
@@ -116,7 +136,7 @@ impl Textures {
 
         // TODO: This needs to be created dynamically to handle different textures & matcaps
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &layout,
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -127,7 +147,7 @@ impl Textures {
                     resource: wgpu::BindingResource::Sampler(sampler),
                 },
             ],
-            label: Some(path),
+            label: Some(source),
         });
 
         queue.write_texture(
@@ -149,15 +169,44 @@ impl Textures {
             size,
         );
 
-        let texture = Texture { bind_group };
+        let texture = Texture {
+            bind_group,
+            width: dimensions.0,
+            height: dimensions.1,
+            format: TEXTURE_FORMAT,
+            source: source.to_string(),
+            is_matcap,
+        };
 
         self.textures.push(texture);
         self.textures.len() - 1
     }
+
+    /// Reloads every texture of a previous instance not already loaded here, in order,
+    /// so existing texture ids stay valid.
+    pub fn restore(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, previous: &Textures) {
+        for texture in previous.textures.iter().skip(self.textures.len()) {
+            self.load_texture(device, queue, &texture.source, texture.is_matcap);
+        }
+    }
 }
 
 pub struct Texture {
     pub bind_group: wgpu::BindGroup,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+
+    // Kept around so the texture can be reloaded if the device is lost
+    pub source: String,
+    pub is_matcap: bool,
+}
+
+impl Texture {
+    pub fn bytes(&self) -> u64 {
+        let block_size = self.format.block_copy_size(None).unwrap_or(4);
+        self.width as u64 * self.height as u64 * block_size as u64
+    }
 }
 
 pub fn sampler_descriptor() -> wgpu::SamplerDescriptor<'static> {
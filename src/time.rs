@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+/// Timing for a single game update. Updates always run at the fixed tick
+/// rate, so `dt` is constant and `frame` counts updates rather than renders.
+#[derive(Clone, Copy, Debug)]
+pub struct GameTime {
+    pub dt: f32,
+    pub elapsed: f32,
+    pub frame: u64,
+}
+
+impl GameTime {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            dt: 1.0 / tick_rate,
+            elapsed: 0.0,
+            frame: 0,
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.frame += 1;
+        self.elapsed = self.frame as f32 * self.dt;
+    }
+}
+
+/// Accumulates real time and hands it out in fixed ticks. If the game falls
+/// too far behind, ie after a breakpoint or a slow load, the missed time is
+/// dropped instead of running a burst of catch up ticks.
+pub struct FixedTimestep {
+    tick: Duration,
+    max_ticks: u32,
+    accumulator: Duration,
+    last: Instant,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f32, max_ticks: u32) -> Self {
+        Self {
+            tick: Duration::from_secs_f32(1.0 / tick_rate),
+            max_ticks,
+            accumulator: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+        self.last = Instant::now();
+    }
+
+    /// Returns how many ticks to run this frame.
+    pub fn advance(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        self.advance_by(elapsed)
+    }
+
+    fn advance_by(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick {
+            self.accumulator -= self.tick;
+            ticks += 1;
+
+            if ticks == self.max_ticks {
+                // Keep the fraction of a tick, so alpha stays smooth
+                let remainder = self.accumulator.as_nanos() % self.tick.as_nanos();
+                self.accumulator = Duration::from_nanos(remainder as u64);
+                break;
+            }
+        }
+        ticks
+    }
+
+    /// How far between the last tick and the next one the frame is, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f32() / self.tick.as_secs_f32()).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn timestep(max_ticks: u32) -> FixedTimestep {
+        FixedTimestep::new(1.0 / TICK.as_secs_f32(), max_ticks)
+    }
+
+    #[test]
+    fn ticks_follow_elapsed_time() {
+        let mut timestep = timestep(5);
+        assert_eq!(timestep.advance_by(TICK / 2), 0);
+        assert_eq!(timestep.advance_by(TICK / 2), 1);
+        assert_eq!(timestep.advance_by(TICK * 3), 3);
+    }
+
+    #[test]
+    fn long_frame_drops_excess_ticks() {
+        let mut timestep = timestep(5);
+        assert_eq!(timestep.advance_by(TICK * 100 + TICK / 4), 5);
+        // Only the fraction of a tick is kept, the rest of the frame is dropped
+        assert!((timestep.alpha() - 0.25).abs() < 0.01);
+        assert_eq!(timestep.advance_by(Duration::ZERO), 0);
+        assert_eq!(timestep.advance_by(TICK), 1);
+    }
+
+    #[test]
+    fn alpha_stays_below_one() {
+        let mut timestep = timestep(3);
+        for n in 0..200 {
+            // Frame times that don't line up with the tick, and some too long to catch up on
+            let elapsed = TICK * (n % 7) + Duration::from_micros(n as u64 * 137 % 10_000);
+            timestep.advance_by(elapsed);
+            let alpha = timestep.alpha();
+            assert!((0.0..1.0).contains(&alpha), "{alpha} after {elapsed:?}");
+        }
+    }
+}
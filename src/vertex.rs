@@ -278,3 +278,15 @@ pub fn model_matrix() -> wgpu::VertexBufferLayout<'static> {
         ],
     }
 }
+
+pub fn object_id() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<u32>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &[wgpu::VertexAttribute {
+            offset: 0,
+            shader_location: 10,
+            format: wgpu::VertexFormat::Uint32,
+        }],
+    }
+}
@@ -1,18 +1,33 @@
-use bytemuck::cast_slice;
-use glam::{Mat4, Vec4Swizzles};
-use wgpu::{RenderPipeline, TextureView};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use glam::{Mat4, Quat, Vec3, Vec4, Vec4Swizzles};
 
 use crate::{
-    camera::Camera,
-    contexts,
-    environment_map::EnvironmentMap,
-    frame_buffer::{FrameBuffer, FRAME_BUFFER_BIND_GROUP_INDEX, SCALING_BIND_GROUP_INDEX},
-    immediate_renderer::ImmediateRenderer,
-    lights::{Light, Lights},
+    camera::{Camera, Frustum, Projection},
+    command_list::CommandList,
+    config::AppConfig,
+    contexts::{self, Record3dContext},
+    display::{DisplaySettings, WindowMode},
+    environment_map::EnvironmentUniforms,
+    frame_buffer,
+    frame_debugger::FrameDebugger,
+    frame_stats::FrameStats,
+    gpu_culling::CullCounts,
+    gpu_timer::{self, GpuTimer, GpuTimings},
+    lights::Light,
+    mesh_pool::MeshRanges,
+    object_ids::ObjectIds,
+    picking::{self, DrawnMesh, MeshHandle, Ray, RayHit},
     pipeline::Pipeline,
     preloaded_renderer::PreloadedRenderer,
-    quad_renderer::QuadRenderer,
-    textures::{self, Textures},
+    render_thread::RenderThread,
+    renderer::{FrameResult, FrameSubmission, RenderTarget, Renderer},
+    resources::{MeshInfo, ResourceReport, ResourceTotals, TextureInfo},
+    stats_overlay::StatsOverlay,
+    textures::Textures,
     virtual_render_pass::{Command, VirtualRenderPass},
 };
 
@@ -22,27 +37,76 @@ pub const MATCAP_BIND_GROUP_INDEX: u32 = 2;
 
 pub const VERTEX_BUFFER_INDEX: u32 = 0;
 pub const INSTANCE_BUFFER_INDEX: u32 = 1;
+pub const OBJECT_ID_BUFFER_INDEX: u32 = 2;
 
+/// The game's side of the gpu. Frames are recorded into a virtual render pass
+/// without touching any gpu buffers, then handed to the render thread while
+/// the next frame is simulated.
 pub struct VirtualGpu {
-    pub device: wgpu::Device,
-    pub queue: wgpu::Queue,
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<wgpu::Queue>,
 
-    pub render_pipelines: [RenderPipeline; 11],
-    pub textures: Textures,
-    pub quad_renderer: QuadRenderer,
-    pub preloaded_renderer: PreloadedRenderer,
-    pub immediate_renderer: ImmediateRenderer,
+    // Shared with the renderer, only written to while loading
+    pub textures: Arc<RwLock<Textures>>,
+    pub preloaded_renderer: Arc<RwLock<PreloadedRenderer>>,
 
     pub camera: Camera,
-    pub lights: Lights,
-
-    pub instance_buffer: wgpu::Buffer,
+    pub environment: EnvironmentUniforms,
     pub virtual_render_pass: VirtualRenderPass,
+    // Passes handed back by the render thread, reused for later frames
+    spare_passes: Vec<VirtualRenderPass>,
+
+    pub display: DisplaySettings,
+    pub asset_root: PathBuf,
+
+    /// Static meshes drawn in the last recorded frame, for cpu picking.
+    pub last_frame_meshes: Vec<DrawnMesh>,
+
+    /// Stats of the last rendered frame, optionally drawn over the frame.
+    pub frame_stats: FrameStats,
+    pub stats_overlay: StatsOverlay,
+    pub show_stats: bool,
+    /// Set by the app to how long the game took to draw the frame, for the stats.
+    pub record_time: Duration,
+    /// Shows how many instances gpu culling culled, over the frame.
+    pub show_culling: bool,
+    gpu_culling: bool,
+    // None until the first counts are read back
+    culled: Option<CullCounts>,
+    cpu_culling: bool,
+    // Computed on the frame's first culled draw, and again only if the camera changes
+    frustum: Option<Frustum>,
+    gpu_timing: bool,
+    gpu_timings: VecDeque<GpuTimings>,
+    supports_markers: bool,
+    pub frame_debugger: FrameDebugger,
+
+    // Object id picking, in frame buffer pixels
+    object_ids_size: Option<(u32, u32)>,
+    pick_request: Option<(u32, u32)>,
+    last_pick: Option<u32>,
+
+    frame_buffer_size: (u32, u32),
+    scaling: [f32; 2],
+    // Taken from the renderer once it's created, it owns them on the render thread
+    buffer_totals: ResourceTotals,
+    environment_texture: TextureInfo,
+    render_thread: RenderThread,
+    // Space of unloaded meshes, freed once every frame submitted before the unload has finished
+    pending_frees: Vec<(u64, MeshRanges)>,
+    frames_submitted: u64,
+    frames_finished: u64,
+}
 
-    pub frame_buffer: FrameBuffer,
-    pub environment_map: EnvironmentMap,
-
-    pub per_frame_bind_group: wgpu::BindGroup,
+/// Resources and settings kept from a previous device, see [`VirtualGpu::into_previous`].
+pub struct PreviousResources {
+    textures: Arc<RwLock<Textures>>,
+    preloaded_renderer: Arc<RwLock<PreloadedRenderer>>,
+    camera: Camera,
+    environment_color_strength: Vec4,
+    display: DisplaySettings,
+    show_stats: bool,
+    show_culling: bool,
 }
 
 impl VirtualGpu {
@@ -50,397 +114,440 @@ impl VirtualGpu {
         device: wgpu::Device,
         queue: wgpu::Queue,
         config: &wgpu::SurfaceConfiguration,
+        display: DisplaySettings,
+        app_config: &AppConfig,
     ) -> Self {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Master Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
+        let device = Arc::new(device);
+        let queue = Arc::new(queue);
 
-        let camera = Camera::new(&device, config);
         let mut textures = Textures::new(&device, config);
-        let lights = Lights::new(&device);
-        let environment_map = EnvironmentMap::new(&device, &queue);
-
-        let per_frame_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Per Frame Bind Group Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // Views
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // Positions
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // Projections
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // Lights
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // Env Map
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 5,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::Cube,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 6,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 7,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-            });
-
-        let per_frame_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Per Frame Bind Group"),
-            layout: &per_frame_bind_group_layout,
-            entries: &[
-                // Camera Bindings
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera.buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: camera.views_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: camera.positions_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: camera.projections_buffer.as_entire_binding(),
-                },
-                // Lights Bindings
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: lights.buffer.as_entire_binding(),
-                },
-                // Env Map
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::TextureView(&environment_map.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::Sampler(&environment_map.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: environment_map.uniforms_buffer.as_entire_binding(),
-                },
-            ],
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &per_frame_bind_group_layout,
-                    &textures.bind_group_layout,
-                    &textures.matcap_bind_group_layout, //Matcap Layout
-                ],
-                push_constant_ranges: &[],
-            });
-
-        textures.load_texture(&device, &queue, "assets/default texture.png", false);
-
-        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Instance Buffer"),
-            size: 8 * 1024 * 1024, // 8mb
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        textures.load_texture(
+            &device,
+            &queue,
+            &path_str(&app_config.asset_path("default texture.png")),
+            false,
+        );
+        textures.load_texture(
+            &device,
+            &queue,
+            &path_str(&app_config.asset_path(&app_config.default_matcap)),
+            true,
+        );
+        let stats_overlay = StatsOverlay::new(&device, &queue, &mut textures);
+
+        let textures = Arc::new(RwLock::new(textures));
+        let preloaded_renderer =
+            Arc::new(RwLock::new(PreloadedRenderer::new(app_config.cpu_picking)));
+        let renderer = Renderer::new(
+            device.clone(),
+            queue.clone(),
+            config,
+            app_config,
+            textures.clone(),
+            preloaded_renderer.clone(),
+        );
 
-        let frame_buffer = FrameBuffer::new(&device, config);
+        let gpu_timing = renderer.gpu_timer.is_some();
+        let gpu_culling = renderer.gpu_culling.is_some();
+        let supports_markers = renderer
+            .gpu_timer
+            .as_ref()
+            .is_some_and(GpuTimer::supports_markers);
+        let object_ids_size = renderer.object_ids.as_ref().map(ObjectIds::size);
+        let frame_buffer_size = renderer.frame_buffer.size();
+        let buffer_totals = renderer.buffer_totals();
+        let environment_texture = renderer.environment_map.info();
 
         Self {
-            render_pipelines: generate_render_pipelines(
-                &device,
-                &shader,
-                &render_pipeline_layout,
-                config.format,
-            ),
-            textures,
-            quad_renderer: QuadRenderer::new(&device, &queue),
-            preloaded_renderer: PreloadedRenderer::new(),
-            immediate_renderer: ImmediateRenderer::new(&device),
-            camera,
-            lights,
             device,
             queue,
-            instance_buffer,
+            textures,
+            preloaded_renderer,
+            camera: Camera::new(config),
+            environment: EnvironmentUniforms::new(),
             virtual_render_pass: VirtualRenderPass::new(),
-            frame_buffer,
-            environment_map,
-            per_frame_bind_group,
+            spare_passes: Vec::new(),
+            display,
+            asset_root: app_config.asset_root.clone(),
+            last_frame_meshes: Vec::new(),
+            frame_stats: FrameStats::default(),
+            stats_overlay,
+            show_stats: app_config.stats_overlay,
+            record_time: Duration::ZERO,
+            show_culling: false,
+            gpu_culling,
+            culled: None,
+            cpu_culling: app_config.cpu_culling,
+            frustum: None,
+            gpu_timing,
+            gpu_timings: VecDeque::new(),
+            supports_markers,
+            frame_debugger: FrameDebugger::default(),
+            object_ids_size,
+            pick_request: None,
+            last_pick: None,
+            frame_buffer_size,
+            scaling: [1.0, 1.0],
+            buffer_totals,
+            environment_texture,
+            render_thread: RenderThread::spawn(renderer, app_config.frames_in_flight),
+            pending_frees: Vec::new(),
+            frames_submitted: 0,
+            frames_finished: 0,
         }
     }
 
-    pub fn render(&mut self, surface_view: &TextureView) {
-        let view = &self.frame_buffer.view;
+    /// Takes what a rebuild restores, dropping the render thread and everything
+    /// else tied to the old device.
+    pub fn into_previous(mut self) -> PreviousResources {
+        self.wait_for_frames();
+        PreviousResources {
+            textures: self.textures,
+            preloaded_renderer: self.preloaded_renderer,
+            camera: self.camera,
+            environment_color_strength: self.environment.environment_color_strength,
+            display: self.display,
+            show_stats: self.show_stats,
+            show_culling: self.show_culling,
+        }
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Main Render Encoder"),
+    /// Rebuilds the resources of a previous virtual gpu, ie after the device was lost.
+    /// Textures and meshes are reloaded from their original sources in the same order,
+    /// so any ids handed out to the game remain valid.
+    pub fn restore_resources(&mut self, previous: &PreviousResources) {
+        self.textures.write().unwrap().restore(
+            &self.device,
+            &self.queue,
+            &previous.textures.read().unwrap(),
+        );
+        self.preloaded_renderer.write().unwrap().restore(
+            &self.device,
+            &self.queue,
+            &previous.preloaded_renderer.read().unwrap(),
+        );
+
+        self.camera.restore(&previous.camera);
+        self.frustum = None;
+        self.environment.environment_color_strength = previous.environment_color_strength;
+        self.display = previous.display;
+        self.show_stats = previous.show_stats;
+        self.show_culling = previous.show_culling;
+    }
+
+    /// Lists every texture and mesh loaded, and the memory used by each kind of buffer.
+    pub fn resources(&self) -> ResourceReport {
+        let textures: Vec<TextureInfo> = self
+            .textures
+            .read()
+            .unwrap()
+            .textures
+            .iter()
+            .enumerate()
+            .map(|(id, texture)| TextureInfo {
+                id: Some(id),
+                width: texture.width,
+                height: texture.height,
+                layers: 1,
+                format: texture.format,
+                is_matcap: texture.is_matcap,
+                source: texture.source.clone(),
+                bytes: texture.bytes(),
+            })
+            .chain([self.environment_texture.clone()])
+            .collect();
+
+        let renderer = self.preloaded_renderer.read().unwrap();
+        let static_meshes = renderer
+            .meshes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, mesh)| {
+                let mesh = mesh.as_ref()?;
+                Some(MeshInfo {
+                    mesh: MeshHandle::Static(index),
+                    pipeline: mesh.pipeline,
+                    vertex_count: mesh.vertex_count,
+                    index_count: None,
+                    bytes: mesh.vertex_count as u64 * mesh.pipeline.get_vertex_size() as u64,
+                    source: mesh.source.clone(),
+                })
             });
+        let indexed_meshes =
+            renderer
+                .indexed_meshes
+                .iter()
+                .enumerate()
+                .filter_map(|(index, mesh)| {
+                    let mesh = mesh.as_ref()?;
+                    Some(MeshInfo {
+                        mesh: MeshHandle::Indexed(index),
+                        pipeline: mesh.pipeline,
+                        vertex_count: mesh.vertex_count,
+                        index_count: Some(mesh.index_count),
+                        bytes: mesh.vertex_count as u64 * mesh.pipeline.get_vertex_size() as u64
+                            + mesh.index_count as u64 * size_of::<u16>() as u64,
+                        source: mesh.source.clone(),
+                    })
+                });
+        let meshes: Vec<MeshInfo> = static_meshes.chain(indexed_meshes).collect();
+
+        // The renderer's own buffers, plus the mesh pool's arenas
+        let buffers = &self.buffer_totals;
+        let totals = ResourceTotals {
+            textures: textures.iter().map(|texture| texture.bytes).sum(),
+            vertex_buffers: renderer.pool.vertex_bytes() + buffers.vertex_buffers,
+            index_buffers: renderer.pool.index_bytes() + buffers.index_buffers,
+            ..*buffers
+        };
+
+        ResourceReport {
+            textures,
+            meshes,
+            totals,
+        }
+    }
 
-        // Game Render Pass
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Main Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.textures.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(f32::NEG_INFINITY),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
+    /// Draws the last frame's stats over this one, call after the game has drawn.
+    pub fn draw_stats_overlay(&mut self) {
+        let mut lines = Vec::new();
+        if self.show_stats {
+            lines = self.frame_stats.lines();
+        }
+        if self.show_culling {
+            lines.push(match self.culled {
+                Some(culled) => format!("GPU CULLED {} OF {}", culled.culled, culled.tested),
+                None if self.gpu_culling => "GPU CULLING PENDING".to_string(),
+                None => "GPU CULLING OFF".to_string(),
             });
+        }
+        if let Some(timings) = self.gpu_timings.back().filter(|_| self.show_stats) {
+            lines.push(format!(
+                "GPU MAIN {:.2} MS FRAME BUFFER {:.2} MS",
+                timings.main_pass.as_secs_f64() * 1000.0,
+                timings.frame_buffer_pass.as_secs_f64() * 1000.0,
+            ));
+            for (label, duration) in timings.ranges.iter() {
+                lines.push(format!(
+                    "  {label} {:.2} MS",
+                    duration.as_secs_f64() * 1000.0
+                ));
+            }
+        }
+        self.virtual_render_pass.object_id = 0;
+        for (matrix, glyph) in self.stats_overlay.layout(&lines) {
+            self.push_matrix(matrix);
+            self.draw_sprite(glyph);
+        }
+    }
 
-            self.queue.write_buffer(
-                &self.camera.buffer,
-                0,
-                bytemuck::cast_slice(&self.camera.get_camera_uniforms()),
-            );
-
-            self.queue.write_buffer(
-                &self.camera.views_buffer,
-                0,
-                bytemuck::bytes_of(&self.camera.get_view()),
-            );
-            self.queue.write_buffer(
-                &self.camera.positions_buffer,
-                0,
-                bytemuck::bytes_of(&self.camera.eye.extend(1.0)),
-            );
-            self.queue.write_buffer(
-                &self.camera.projections_buffer,
-                0,
-                bytemuck::bytes_of(&self.camera.get_projection_3d()),
-            );
-
-            render_pass.set_bind_group(PER_FRAME_BIND_GROUP_INDEX, &self.per_frame_bind_group, &[]);
-            self.queue.write_buffer(
-                &self.environment_map.uniforms_buffer,
-                0,
-                bytemuck::cast_slice(&self.environment_map.uniforms.get_uniforms()),
-            );
-            render_pass.set_vertex_buffer(INSTANCE_BUFFER_INDEX, self.instance_buffer.slice(..));
-
-            self.virtual_render_pass.execute(&mut render_pass, self);
+    /// Freezes the last frame and prints its commands, or goes back to running the game.
+    pub fn toggle_frame_debugger(&mut self) {
+        // The frame to freeze may still be on the render thread
+        self.wait_for_frames();
+        if !self.frame_debugger.toggle() {
+            println!("No frame to debug yet.");
+            return;
+        }
+        if self.frame_debugger.is_frozen() {
+            let textures = self.textures.read().unwrap();
+            let meshes = self.preloaded_renderer.read().unwrap();
+            for line in self.frame_debugger.listing(&textures, &meshes) {
+                println!("{line}");
+            }
         }
+    }
 
-        // Frame Buffer Render Pass
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Frame Buffer Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: surface_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+    /// Replays the frozen frame up to the selected command, with the command list over it.
+    pub fn draw_frame_debugger(&mut self) {
+        let lines = self.frame_debugger.lines(
+            &self.textures.read().unwrap(),
+            &self.preloaded_renderer.read().unwrap(),
+        );
+        self.frame_debugger.replay(&mut self.virtual_render_pass);
+
+        self.virtual_render_pass.object_id = 0;
+        for (matrix, glyph) in self.stats_overlay.layout(&lines) {
+            self.push_matrix(matrix);
+            self.draw_sprite(glyph);
+        }
+    }
+
+    fn view_space_light(&self, light: &Light) -> Light {
+        let mut light = *light;
+        let view_position = self.camera.get_view() * light.position_range.xyz().extend(1.0);
+        let view_direction = self.camera.get_view() * light.direction_min_angle.xyz().extend(0.0);
 
-            render_pass.set_pipeline(&self.frame_buffer.pipeline);
-            self.queue.write_buffer(
-                &self.frame_buffer.scaling_buffer,
-                0,
-                bytemuck::cast_slice(&self.frame_buffer.scaling),
-            );
-            render_pass.set_bind_group(
-                SCALING_BIND_GROUP_INDEX,
-                &self.frame_buffer.scaling_bind_group,
-                &[],
-            );
-            render_pass.set_bind_group(
-                FRAME_BUFFER_BIND_GROUP_INDEX,
-                &self.frame_buffer.texture_bind_group,
-                &[],
-            );
-            render_pass.draw(0..4, 0..1);
+        light.position_range = view_position.xyz().extend(light.position_range.w);
+        light.direction_min_angle = view_direction.xyz().extend(light.direction_min_angle.w);
+        light
+    }
+
+    /// Called when the window is resized, the frame buffer keeps its resolution
+    /// and is letterboxed into the window.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.scaling = frame_buffer::letterbox_scaling(self.frame_buffer_size, width, height);
+        self.camera.set_surface(width, height, self.scaling);
+        self.frustum = None;
+    }
+
+    /// Hands the recorded frame to the render thread, and starts recording the
+    /// next one. Returns the surface error of any frame that couldn't be drawn.
+    pub fn render(&mut self, target: RenderTarget) -> Result<(), wgpu::SurfaceError> {
+        let spare = self
+            .spare_passes
+            .pop()
+            .unwrap_or_else(VirtualRenderPass::new);
+        let mut pass = std::mem::replace(&mut self.virtual_render_pass, spare);
+        std::mem::swap(&mut self.last_frame_meshes, &mut pass.drawn_meshes);
+        pass.drawn_meshes.clear();
+        self.frustum = None;
+
+        let frame = FrameSubmission {
+            pass,
+            camera: self.camera.frame(),
+            environment: self.environment.get_uniforms(),
+            scaling: self.scaling,
+            pick: self.pick_request.take(),
+            target,
+            record_time: std::mem::take(&mut self.record_time),
+        };
+
+        self.frames_submitted += 1;
+        let mut result = Ok(());
+        for finished in self.render_thread.submit(frame) {
+            if let Some(e) = self.finish_frame(finished) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Waits for the render thread to finish every frame handed to it.
+    pub fn wait_for_frames(&mut self) {
+        for finished in self.render_thread.wait() {
+            self.finish_frame(finished);
+        }
+    }
+
+    // The frustum static mesh draws are culled against, None without cpu culling
+    fn culling_frustum(&mut self) -> Option<Frustum> {
+        if !self.cpu_culling {
+            return None;
         }
+        Some(*self.frustum.get_or_insert_with(|| self.camera.frustum()))
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        self.virtual_render_pass.reset();
+    // Static mesh draws outside the camera frustum are dropped as they're recorded
+    fn is_visible(&mut self, mesh: MeshHandle) -> bool {
+        let Some(frustum) = self.culling_frustum() else {
+            return true;
+        };
+        let meshes = self.preloaded_renderer.read().unwrap();
+        let matrix = self.virtual_render_pass.model_matrix;
+        is_visible(
+            &mut self.virtual_render_pass,
+            &meshes,
+            &frustum,
+            mesh,
+            matrix,
+        )
+    }
+
+    fn finish_frame(&mut self, finished: FrameResult) -> Option<wgpu::SurfaceError> {
+        let FrameResult {
+            mut pass,
+            stats,
+            gpu_timings,
+            pick,
+            culled,
+            error,
+        } = finished;
+
+        if error.is_none() {
+            self.frame_stats = stats;
+            self.last_pick = pick;
+            self.culled = culled;
+            for timings in gpu_timings {
+                if self.gpu_timings.len() == gpu_timer::HISTORY_LENGTH {
+                    self.gpu_timings.pop_front();
+                }
+                self.gpu_timings.push_back(timings);
+            }
+            self.frame_debugger.capture(&mut pass);
+        }
+
+        pass.reset();
+        self.spare_passes.push(pass);
+
+        // Frames finish in the order they were submitted
+        self.frames_finished += 1;
+        if self
+            .pending_frees
+            .iter()
+            .any(|(submitted, _)| *submitted <= self.frames_finished)
+        {
+            let mut meshes = self.preloaded_renderer.write().unwrap();
+            let finished = self.frames_finished;
+            for (_, ranges) in self
+                .pending_frees
+                .extract_if(.., |(submitted, _)| *submitted <= finished)
+            {
+                meshes.pool.free(ranges);
+            }
+        }
+        error
     }
 }
 
-fn generate_render_pipelines(
-    device: &wgpu::Device,
-    shader: &wgpu::ShaderModule,
-    layout: &wgpu::PipelineLayout,
-    format: wgpu::TextureFormat,
-) -> [RenderPipeline; 11] {
-    const PIPELINES: [Pipeline; 11] = [
-        Pipeline::Color,
-        Pipeline::Uv,
-        Pipeline::ColorUv,
-        Pipeline::ColorLit,
-        Pipeline::UvLit,
-        Pipeline::ColorUvLit,
-        Pipeline::Quad2d,
-        Pipeline::Matcap,
-        Pipeline::MatcapColor,
-        Pipeline::MatcapUv,
-        Pipeline::MatcapColorUv,
-    ];
-
-    std::array::from_fn(|i| {
-        let pipeline = PIPELINES[i];
-
-        create_render_pipeline(device, shader, layout, format, pipeline)
-    })
+fn path_str(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
 }
 
-fn create_render_pipeline(
-    device: &wgpu::Device,
-    shader: &wgpu::ShaderModule,
-    layout: &wgpu::PipelineLayout,
-    format: wgpu::TextureFormat,
-    pipeline: Pipeline,
-) -> RenderPipeline {
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some(pipeline.name()),
-        layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: shader,
-            entry_point: Some(pipeline.vertex_shader()),
-            buffers: &pipeline.get_pipeline_buffers(),
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: shader,
-            entry_point: Some(pipeline.fragment_shader()),
-            targets: &[Some(wgpu::ColorTargetState {
-                format,
-                blend: Some(wgpu::BlendState::REPLACE),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            polygon_mode: wgpu::PolygonMode::Fill,
-            unclipped_depth: false,
-            conservative: false,
-        },
-        depth_stencil: Some(wgpu::DepthStencilState {
-            format: textures::DepthTexture::DEPTH_FORMAT,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::GreaterEqual,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        },
-        multiview: None,
-        cache: None,
-    })
+// Counts the test in the pass. Unloaded meshes are left for the renderer to
+// skip, and 2d meshes aren't drawn with the camera
+fn is_visible(
+    pass: &mut VirtualRenderPass,
+    meshes: &PreloadedRenderer,
+    frustum: &Frustum,
+    mesh: MeshHandle,
+    matrix: Mat4,
+) -> bool {
+    let (Some(bounds), Some(pipeline)) = (meshes.bounds(mesh), meshes.pipeline(mesh)) else {
+        return true;
+    };
+    if pipeline == Pipeline::Quad2d {
+        return true;
+    }
+
+    pass.tested_meshes += 1;
+    let visible = bounds.is_visible(frustum, matrix);
+    if !visible {
+        pass.culled_meshes += 1;
+    }
+    visible
 }
 
 impl contexts::Init3dContext for VirtualGpu {
+    fn asset_path(&self, path: &str) -> PathBuf {
+        self.asset_root.join(path)
+    }
+
     fn load_texture(&mut self, path: &str, is_matcap: bool) -> usize {
+        let path = path_str(&self.asset_path(path));
         self.textures
-            .load_texture(&self.device, &self.queue, path, is_matcap)
+            .write()
+            .unwrap()
+            .load_texture(&self.device, &self.queue, &path, is_matcap)
     }
 
     fn load_static_mesh(&mut self, data: &[f32], pipeline: Pipeline) -> usize {
-        self.preloaded_renderer
-            .load_static_mesh(&self.device, &self.queue, data, pipeline)
+        self.preloaded_renderer.write().unwrap().load_static_mesh(
+            &self.device,
+            &self.queue,
+            data,
+            pipeline,
+        )
     }
 
     fn load_static_mesh_indexed(
@@ -449,19 +556,41 @@ impl contexts::Init3dContext for VirtualGpu {
         indices: &[u16],
         pipeline: Pipeline,
     ) -> usize {
-        self.preloaded_renderer.load_static_mesh_indexed(
-            &self.device,
-            &self.queue,
-            data,
-            indices,
-            pipeline,
-        )
+        self.preloaded_renderer
+            .write()
+            .unwrap()
+            .load_static_mesh_indexed(&self.device, &self.queue, data, indices, pipeline)
+    }
+
+    fn unload_mesh(&mut self, mesh: MeshHandle) {
+        if let Some(ranges) = self.preloaded_renderer.write().unwrap().unload(mesh) {
+            self.pending_frees.push((self.frames_submitted, ranges));
+        }
+    }
+
+    fn set_mesh_source(&mut self, mesh: MeshHandle, source: &str) {
+        self.preloaded_renderer
+            .write()
+            .unwrap()
+            .set_source(mesh, source);
     }
 }
 
-impl contexts::Draw3dContext for VirtualGpu {
-    fn get_camera(&self) -> &Camera {
-        &self.camera
+impl contexts::Record3dContext for VirtualGpu {
+    fn gpu_marker(&mut self, label: &'static str) {
+        let markers = &mut self.virtual_render_pass.markers;
+        if !self.supports_markers || markers.len() == gpu_timer::MAX_MARKERS {
+            return;
+        }
+
+        self.virtual_render_pass
+            .commands
+            .push(Command::GpuMarker(markers.len()));
+        markers.push(label);
+    }
+
+    fn set_object_id(&mut self, id: u32) {
+        self.virtual_render_pass.object_id = id;
     }
 
     fn draw_tri_list(&mut self, data: &[f32], pipeline: Pipeline) {
@@ -469,16 +598,14 @@ impl contexts::Draw3dContext for VirtualGpu {
         let total_attributes = data.len();
         let vertex_count = total_attributes / attribute_count;
 
-        if total_attributes % attribute_count != 0 {
+        if !total_attributes.is_multiple_of(attribute_count) {
             println!("Invalid triangle list, size mismatch");
             return;
         }
 
-        self.queue.write_buffer(
-            &self.immediate_renderer.buffer,
-            self.virtual_render_pass.immediate_buffer_last_index,
-            bytemuck::cast_slice(data),
-        );
+        self.virtual_render_pass
+            .immediate_data
+            .extend_from_slice(data);
 
         self.virtual_render_pass
             .commands
@@ -486,47 +613,41 @@ impl contexts::Draw3dContext for VirtualGpu {
         self.virtual_render_pass
             .commands
             .push(Command::Draw(vertex_count as u32));
-        self.virtual_render_pass.immediate_buffer_last_index += total_attributes as u64 * 4;
     }
 
     fn push_light(&mut self, light: &Light) {
-        let offset = self.virtual_render_pass.light_count * size_of::<Light>() as u64;
-        let mut light = *light;
-        let view_position = self.camera.get_view() * light.position_range.xyz().extend(1.0);
-        let view_direction = self.camera.get_view() * light.direction_min_angle.xyz().extend(0.0);
-
-        light.position_range = view_position.xyz().extend(light.position_range.w);
-        light.direction_min_angle = view_direction.xyz().extend(light.direction_min_angle.w);
-
-        self.queue.write_buffer(
-            &self.lights.buffer,
-            offset,
-            cast_slice(&light.get_light_uniforms()),
-        );
-
-        self.virtual_render_pass.light_count += 1;
+        let light = self.view_space_light(light);
+        self.virtual_render_pass.lights.push(light);
     }
 
     fn push_matrix(&mut self, matrix: Mat4) {
-        let offset = self.virtual_render_pass.inistance_count * size_of::<Mat4>() as u64;
-        self.queue
-            .write_buffer(&self.instance_buffer, offset, bytemuck::bytes_of(&matrix));
-        self.virtual_render_pass
-            .commands
-            .push(Command::SetModelMatrix);
-        self.virtual_render_pass.inistance_count += 1;
+        let pass = &mut self.virtual_render_pass;
+        pass.commands.push(Command::SetModelMatrix);
+        pass.model_matrix = matrix;
+        pass.matrices.push(matrix);
+        pass.object_ids.push(pass.object_id);
     }
 
     fn draw_static_mesh(&mut self, index: usize) {
+        if !self.is_visible(MeshHandle::Static(index)) {
+            return;
+        }
         self.virtual_render_pass
             .commands
-            .push(Command::DrawStaticMesh(index))
+            .push(Command::DrawStaticMesh(index));
+        self.virtual_render_pass
+            .push_drawn_mesh(MeshHandle::Static(index));
     }
 
     fn draw_static_mesh_indexed(&mut self, index: usize) {
+        if !self.is_visible(MeshHandle::Indexed(index)) {
+            return;
+        }
         self.virtual_render_pass
             .commands
-            .push(Command::DrawStaticMeshIndexed(index))
+            .push(Command::DrawStaticMeshIndexed(index));
+        self.virtual_render_pass
+            .push_drawn_mesh(MeshHandle::Indexed(index));
     }
 
     fn draw_sprite(&mut self, index: usize) {
@@ -547,3 +668,160 @@ impl contexts::Draw3dContext for VirtualGpu {
             .push(Command::SetMatcap(matcap_id))
     }
 }
+
+impl contexts::Draw3dContext for VirtualGpu {
+    fn get_camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    fn gpu_timings(&self) -> Option<&VecDeque<GpuTimings>> {
+        self.gpu_timing.then_some(&self.gpu_timings)
+    }
+
+    fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        picking::pick(
+            ray,
+            &self.last_frame_meshes,
+            &self.preloaded_renderer.read().unwrap(),
+        )
+    }
+
+    fn pick(&mut self, x: f32, y: f32) -> Option<u32> {
+        let ndc = self.camera.screen_to_ndc(x, y)?;
+        let (width, height) = self.object_ids_size?;
+
+        let x = ((ndc.x + 1.0) / 2.0 * width as f32) as u32;
+        let y = ((1.0 - ndc.y) / 2.0 * height as f32) as u32;
+        if x < width && y < height {
+            self.pick_request = Some((x, y));
+        }
+        self.last_pick
+    }
+
+    fn set_camera_transform(&mut self, transform: Mat4) {
+        self.camera.set_transform(transform);
+        self.frustum = None;
+    }
+
+    fn set_camera(&mut self, position: Vec3, orientation: Quat) {
+        self.camera.set(position, orientation);
+        self.frustum = None;
+    }
+
+    fn look_at(&mut self, eye: Vec3, target: Vec3, up: Vec3) {
+        self.camera.look_at(eye, target, up);
+        self.frustum = None;
+    }
+
+    fn set_fov(&mut self, fovy: f32) {
+        self.camera.set_fov(fovy);
+        self.frustum = None;
+    }
+
+    fn set_near(&mut self, z_near: f32) {
+        self.camera.set_near(z_near);
+        self.frustum = None;
+    }
+
+    fn set_projection(&mut self, projection: Projection) {
+        self.camera.set_projection(projection);
+        self.frustum = None;
+    }
+
+    fn append_command_list(&mut self, list: &CommandList) {
+        let lights: Vec<Light> = list
+            .lights
+            .iter()
+            .map(|light| self.view_space_light(light))
+            .collect();
+        let frustum = self.culling_frustum();
+        let meshes = self.preloaded_renderer.read().unwrap();
+        let pass = &mut self.virtual_render_pass;
+        // Draws are culled with the matrix they'd be drawn with
+        let mut matrix = pass.model_matrix;
+        let mut instance = 0;
+
+        pass.immediate_data.extend_from_slice(&list.immediate_data);
+        pass.lights.extend(lights);
+
+        let first_instance = pass.matrices.len() as u32;
+        pass.matrices.extend_from_slice(&list.matrices);
+        pass.object_ids.extend_from_slice(&list.object_ids);
+        if let Some(matrix) = list.matrices.last() {
+            pass.model_matrix = *matrix;
+        }
+
+        // The list keeps a drawn mesh for each draw after its first matrix, in order.
+        // Draws before it are picked with the matrix current when the list is appended
+        let mut drawn_meshes = list.drawn_meshes.iter();
+
+        // Markers are numbered by the frame, and limited like the gpu's own
+        let first_marker = pass.markers.len();
+        for command in list.commands.iter() {
+            match *command {
+                Command::GpuMarker(marker) => {
+                    if self.supports_markers && pass.markers.len() < gpu_timer::MAX_MARKERS {
+                        pass.commands
+                            .push(Command::GpuMarker(first_marker + marker));
+                        pass.markers.push(list.markers[marker]);
+                    }
+                }
+                Command::SetModelMatrix => {
+                    matrix = list.matrices[instance];
+                    instance += 1;
+                    pass.commands.push(Command::SetModelMatrix);
+                }
+                Command::DrawStaticMesh(index) | Command::DrawStaticMeshIndexed(index) => {
+                    let mesh = match command {
+                        Command::DrawStaticMesh(_) => MeshHandle::Static(index),
+                        _ => MeshHandle::Indexed(index),
+                    };
+                    let visible = frustum
+                        .as_ref()
+                        .is_none_or(|frustum| is_visible(pass, &meshes, frustum, mesh, matrix));
+                    let drawn = if instance > 0 {
+                        drawn_meshes.next().map(|drawn| DrawnMesh {
+                            instance: drawn.instance + first_instance,
+                            ..*drawn
+                        })
+                    } else {
+                        Some(DrawnMesh {
+                            mesh,
+                            instance: first_instance.saturating_sub(1),
+                            matrix,
+                        })
+                    };
+                    if visible {
+                        pass.commands.push(*command);
+                        pass.drawn_meshes.extend(drawn);
+                    }
+                }
+                command => pass.commands.push(command),
+            }
+        }
+    }
+
+    fn get_display_settings(&self) -> DisplaySettings {
+        self.display
+    }
+
+    fn set_window_mode(&mut self, mode: WindowMode) {
+        self.display.window_mode = mode;
+    }
+
+    fn set_present_mode(&mut self, mode: wgpu::PresentMode) {
+        self.display.present_mode = mode;
+    }
+
+    fn set_frame_latency(&mut self, latency: u32) {
+        self.display.frame_latency = latency;
+    }
+
+    fn set_cursor_grab(&mut self, grab: bool) {
+        self.display.cursor_grab = grab;
+    }
+}
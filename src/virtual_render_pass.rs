@@ -1,19 +1,48 @@
+use glam::{Mat4, Vec4};
+use wgpu::util::DrawIndexedIndirectArgs;
+
 use crate::{
+    frame_stats::FrameStats,
+    indirect_draws::{IndirectBatch, IndirectDrawList, MAX_INDIRECT_DRAWS},
+    lights::Light,
+    picking::{DrawnMesh, MeshHandle},
     pipeline::Pipeline,
-    virtual_gpu::{
-        VirtualGpu, MATCAP_BIND_GROUP_INDEX, TEXTURE_BIND_GROUP_INDEX, VERTEX_BUFFER_INDEX,
-    },
+    preloaded_renderer::PreloadedRenderer,
+    renderer::Renderer,
+    textures::Textures,
+    virtual_gpu::{MATCAP_BIND_GROUP_INDEX, TEXTURE_BIND_GROUP_INDEX, VERTEX_BUFFER_INDEX},
 };
 
+/// A frame being recorded. Vertex data, matrices and lights are staged here,
+/// and only uploaded once the render thread takes the frame, so the game
+/// never writes to buffers the gpu may be reading.
+#[derive(Clone)]
 pub struct VirtualRenderPass {
     pub commands: Vec<Command>,
 
-    pub immediate_buffer_last_index: u64,
-    pub inistance_count: u64,
+    pub immediate_data: Vec<f32>,
+    pub matrices: Vec<Mat4>,
+    // One per matrix, like the object id buffer
+    pub object_ids: Vec<u32>,
+    // Already in view space
+    pub lights: Vec<Light>,
+
+    // Static mesh draws and the last pushed matrix, kept for cpu picking
+    pub drawn_meshes: Vec<DrawnMesh>,
+    pub model_matrix: Mat4,
+
+    // Written alongside each pushed matrix, when object ids are enabled
+    pub object_id: u32,
+
+    // Static mesh draws tested against the camera frustum, and those culled
+    pub tested_meshes: u32,
+    pub culled_meshes: u32,
 
-    pub light_count: u64,
+    // Labels of the gpu timing markers recorded this frame
+    pub markers: Vec<&'static str>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Command {
     SetPipeline(Pipeline),
     Draw(u32),         //Vertex Count
@@ -23,41 +52,275 @@ pub enum Command {
     DrawStaticMesh(usize),        // Static Mesh ID
     DrawStaticMeshIndexed(usize), // Static Mesh Indexed Id
     DrawSprite(usize),
+    GpuMarker(usize), // Index into the frame's markers
+    Highlight,        // Redraws the last draw over everything, for the frame debugger
+}
+
+impl Command {
+    pub fn is_draw(&self) -> bool {
+        matches!(
+            self,
+            Command::Draw(_)
+                | Command::DrawStaticMesh(_)
+                | Command::DrawStaticMeshIndexed(_)
+                | Command::DrawSprite(_)
+        )
+    }
+}
+
+// Enough to repeat a draw, for highlighting it
+#[derive(Clone, Copy)]
+struct LastDraw {
+    command: Command,
+    pipeline: Pipeline,
+    byte_index: u64,
+    instance: u32,
+}
+
+// What's bound to the vertex and index slots
+#[derive(Clone, Copy, PartialEq)]
+enum BufferBinding {
+    Arena(usize),
+    Quad,
+}
+
+// State bound in the render pass, so draws only set what changed
+#[derive(Default)]
+struct Bindings {
+    pipeline: Option<Pipeline>,
+    // None after an immediate draw, or when unknown
+    vertex: Option<BufferBinding>,
+    index: Option<BufferBinding>,
+    texture: Option<usize>,
+    matcap: Option<usize>,
+}
+
+impl Bindings {
+    fn set_pipeline(
+        &mut self,
+        rp: &mut wgpu::RenderPass,
+        gpu: &Renderer,
+        pipeline: Pipeline,
+        stats: &mut FrameStats,
+    ) {
+        if self.pipeline != Some(pipeline) {
+            rp.set_pipeline(&gpu.render_pipelines[pipeline.get_shader()]);
+            self.pipeline = Some(pipeline);
+            stats.pipeline_switches += 1;
+        }
+    }
+
+    fn set_texture(
+        &mut self,
+        rp: &mut wgpu::RenderPass,
+        textures: &Textures,
+        texture: usize,
+        stats: &mut FrameStats,
+    ) {
+        if self.texture != Some(texture) {
+            let bind_group = &textures.textures[texture].bind_group;
+            rp.set_bind_group(TEXTURE_BIND_GROUP_INDEX, bind_group, &[]);
+            self.texture = Some(texture);
+            stats.bind_group_switches += 1;
+        }
+    }
+
+    fn set_matcap(
+        &mut self,
+        rp: &mut wgpu::RenderPass,
+        textures: &Textures,
+        matcap: usize,
+        stats: &mut FrameStats,
+    ) {
+        if self.matcap != Some(matcap) {
+            let bind_group = &textures.textures[matcap].bind_group;
+            rp.set_bind_group(MATCAP_BIND_GROUP_INDEX, bind_group, &[]);
+            self.matcap = Some(matcap);
+            stats.bind_group_switches += 1;
+        }
+    }
+
+    fn set_buffers(
+        &mut self,
+        rp: &mut wgpu::RenderPass,
+        gpu: &Renderer,
+        meshes: &PreloadedRenderer,
+        binding: BufferBinding,
+        indexed: bool,
+    ) {
+        if self.vertex != Some(binding) {
+            let buffer = match binding {
+                BufferBinding::Arena(arena) => &meshes.pool.arenas[arena].vertex_buffer,
+                BufferBinding::Quad => &gpu.quad_renderer.quad_vertex_buffer,
+            };
+            rp.set_vertex_buffer(VERTEX_BUFFER_INDEX, buffer.slice(..));
+            self.vertex = Some(binding);
+        }
+        if indexed && self.index != Some(binding) {
+            let buffer = match binding {
+                BufferBinding::Arena(arena) => &meshes.pool.arenas[arena].index_buffer,
+                BufferBinding::Quad => &gpu.quad_renderer.quad_index_buffer,
+            };
+            rp.set_index_buffer(buffer.slice(..), wgpu::IndexFormat::Uint16);
+            self.index = Some(binding);
+        }
+    }
 }
 
 impl VirtualRenderPass {
     pub fn new() -> Self {
         Self {
             commands: Vec::new(),
-            inistance_count: 0,
-            light_count: 0,
-            immediate_buffer_last_index: 0,
+            immediate_data: Vec::new(),
+            matrices: Vec::new(),
+            object_ids: Vec::new(),
+            lights: Vec::new(),
+            drawn_meshes: Vec::new(),
+            model_matrix: Mat4::IDENTITY,
+            object_id: 0,
+            tested_meshes: 0,
+            culled_meshes: 0,
+            markers: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) {
         self.commands.clear();
-        self.inistance_count = 0;
-        self.light_count = 0;
-        self.immediate_buffer_last_index = 0;
+        self.immediate_data.clear();
+        self.matrices.clear();
+        self.object_ids.clear();
+        self.lights.clear();
+        self.drawn_meshes.clear();
+        self.model_matrix = Mat4::IDENTITY;
+        self.object_id = 0;
+        self.tested_meshes = 0;
+        self.culled_meshes = 0;
+        self.markers.clear();
     }
 
-    pub fn execute(&self, rp: &mut wgpu::RenderPass, gpu: &VirtualGpu) {
+    pub fn push_drawn_mesh(&mut self, mesh: MeshHandle) {
+        self.drawn_meshes.push(DrawnMesh {
+            mesh,
+            instance: self.matrices.len().saturating_sub(1) as u32,
+            matrix: self.model_matrix,
+        });
+    }
+
+    /// The indexed static mesh draws, in the order they're drawn, for drawing
+    /// them indirectly. Draws are batched while only matrices change between them.
+    pub fn indirect_draws(&self, meshes: &PreloadedRenderer) -> IndirectDrawList {
+        let mut list = IndirectDrawList::default();
+        let mut instance_count = 0;
+        let mut batch: Option<IndirectBatch> = None;
+        for command in self.commands.iter() {
+            match command {
+                Command::SetModelMatrix => instance_count += 1,
+                Command::DrawStaticMeshIndexed(index) => {
+                    let Some(mesh) = &meshes.indexed_meshes[*index] else {
+                        continue;
+                    };
+                    // Later draws are drawn directly
+                    if list.args.len() == MAX_INDIRECT_DRAWS {
+                        list.batches.extend(batch.take());
+                        continue;
+                    }
+
+                    let draw = list.args.len() as u32;
+                    match &mut batch {
+                        Some(batch)
+                            if batch.pipeline == mesh.pipeline && batch.arena == mesh.arena =>
+                        {
+                            batch.count += 1;
+                        }
+                        _ => {
+                            list.batches.extend(batch.take());
+                            batch = Some(IndirectBatch {
+                                pipeline: mesh.pipeline,
+                                arena: mesh.arena,
+                                first: draw,
+                                count: 1,
+                            });
+                        }
+                    }
+
+                    list.args.push(DrawIndexedIndirectArgs {
+                        index_count: mesh.index_count,
+                        instance_count: 1,
+                        first_index: mesh.first_index,
+                        base_vertex: mesh.base_vertex as i32,
+                        first_instance: instance_count - 1,
+                    });
+                    list.draw_batches.push(list.batches.len() as u32);
+                    // 2d meshes aren't drawn with the 3d camera, a negative radius is never culled
+                    list.spheres.push(if mesh.pipeline == Pipeline::Quad2d {
+                        Vec4::NEG_ONE
+                    } else {
+                        mesh.bounds.sphere()
+                    });
+                }
+                _ => list.batches.extend(batch.take()),
+            }
+        }
+        list.batches.extend(batch);
+        list
+    }
+
+    pub fn execute(
+        &self,
+        rp: &mut wgpu::RenderPass,
+        gpu: &Renderer,
+        textures: &Textures,
+        meshes: &PreloadedRenderer,
+        indirect: &IndirectDrawList,
+        stats: &mut FrameStats,
+    ) {
         let mut current_byte_index = 0;
         let mut current_vertex_size = 0;
         let mut current_model_matrix = 0;
+        let mut current_pipeline = None;
+        let mut last_draw = None;
+        let mut bindings = Bindings::default();
+        // Each batch is drawn with its last draw
+        let mut indirect_draw = 0;
+        let mut next_batch = 0;
 
         for command in self.commands.iter() {
+            stats.commands.count(command);
+            if command.is_draw() {
+                let pipeline = match command {
+                    Command::DrawStaticMesh(index) => meshes.pipeline(MeshHandle::Static(*index)),
+                    Command::DrawStaticMeshIndexed(index) => {
+                        meshes.pipeline(MeshHandle::Indexed(*index))
+                    }
+                    Command::DrawSprite(_) => Some(Pipeline::Quad2d),
+                    _ => Some(current_pipeline.unwrap_or(Pipeline::Color)),
+                };
+                // Unloaded meshes are skipped, frames recorded before the unload may still draw them
+                let Some(pipeline) = pipeline else {
+                    continue;
+                };
+                last_draw = Some(LastDraw {
+                    command: *command,
+                    pipeline,
+                    byte_index: current_byte_index,
+                    instance: current_model_matrix - 1,
+                });
+            }
+
             match command {
                 Command::SetPipeline(pipeline) => {
-                    rp.set_pipeline(&gpu.render_pipelines[pipeline.get_shader()]);
+                    bindings.set_pipeline(rp, gpu, *pipeline, stats);
                     current_vertex_size = pipeline.get_vertex_size();
+                    current_pipeline = Some(*pipeline);
                 }
                 Command::Draw(vertex_count) => {
+                    stats.draw_calls += 1;
+                    stats.triangles += *vertex_count as u64 / 3;
                     rp.set_vertex_buffer(
                         VERTEX_BUFFER_INDEX,
                         gpu.immediate_renderer.buffer.slice(current_byte_index..),
                     );
+                    bindings.vertex = None;
                     rp.draw(
                         0..*vertex_count,
                         current_model_matrix - 1..current_model_matrix,
@@ -65,51 +328,128 @@ impl VirtualRenderPass {
                     current_byte_index += *vertex_count as u64 * current_vertex_size as u64;
                 }
                 Command::SetTexture(tex_index) => {
-                    let texture = &gpu.textures.textures[*tex_index];
-                    rp.set_bind_group(TEXTURE_BIND_GROUP_INDEX, &texture.bind_group, &[]);
+                    bindings.set_texture(rp, textures, *tex_index, stats);
                 }
                 Command::SetMatcap(matcap_index) => {
-                    let matcap = &gpu.textures.textures[*matcap_index];
-                    rp.set_bind_group(MATCAP_BIND_GROUP_INDEX, &matcap.bind_group, &[]);
+                    bindings.set_matcap(rp, textures, *matcap_index, stats);
                 }
                 Command::SetModelMatrix => {
                     current_model_matrix += 1;
                 }
                 Command::DrawStaticMesh(index) => {
-                    let mesh = &gpu.preloaded_renderer.meshes[*index];
-                    rp.set_pipeline(&gpu.render_pipelines[mesh.pipeline.get_shader()]);
-                    rp.set_vertex_buffer(VERTEX_BUFFER_INDEX, mesh.vertex_buffer.slice(..));
+                    let Some(mesh) = &meshes.meshes[*index] else {
+                        continue;
+                    };
+                    stats.draw_calls += 1;
+                    stats.triangles += mesh.vertex_count as u64 / 3;
+                    bindings.set_pipeline(rp, gpu, mesh.pipeline, stats);
+                    bindings.set_buffers(rp, gpu, meshes, BufferBinding::Arena(mesh.arena), false);
                     rp.draw(
-                        0..mesh.vertex_count,
+                        mesh.vertices(),
                         current_model_matrix - 1..current_model_matrix,
                     );
                 }
                 Command::DrawStaticMeshIndexed(index) => {
-                    let mesh = &gpu.preloaded_renderer.indexed_meshes[*index];
-                    rp.set_pipeline(&gpu.render_pipelines[mesh.pipeline.get_shader()]);
-                    rp.set_vertex_buffer(VERTEX_BUFFER_INDEX, mesh.vertex_buffer.slice(..));
-                    rp.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                    let Some(mesh) = &meshes.indexed_meshes[*index] else {
+                        continue;
+                    };
+                    stats.draw_calls += 1;
+                    stats.triangles += mesh.index_count as u64 / 3;
+
+                    if indirect_draw < indirect.args.len() as u32 {
+                        indirect_draw += 1;
+                        let batch = indirect.batches[next_batch];
+                        if indirect_draw == batch.first + batch.count {
+                            draw_batch(rp, gpu, meshes, &mut bindings, next_batch, batch, stats);
+                            next_batch += 1;
+                        }
+                        continue;
+                    }
+
+                    bindings.set_pipeline(rp, gpu, mesh.pipeline, stats);
+                    bindings.set_buffers(rp, gpu, meshes, BufferBinding::Arena(mesh.arena), true);
                     rp.draw_indexed(
-                        0..mesh.index_count,
-                        0,
+                        mesh.index_range(),
+                        mesh.base_vertex as i32,
                         current_model_matrix - 1..current_model_matrix,
                     );
                 }
                 Command::DrawSprite(sprite_index) => {
-                    let texture = &gpu.textures.textures[*sprite_index];
-                    rp.set_pipeline(&gpu.render_pipelines[Pipeline::Quad2d.get_shader()]);
-                    rp.set_bind_group(TEXTURE_BIND_GROUP_INDEX, &texture.bind_group, &[]);
-                    rp.set_index_buffer(
-                        gpu.quad_renderer.quad_index_buffer.slice(..),
-                        wgpu::IndexFormat::Uint16,
-                    );
-                    rp.set_vertex_buffer(
-                        VERTEX_BUFFER_INDEX,
-                        gpu.quad_renderer.quad_vertex_buffer.slice(..),
-                    );
+                    stats.draw_calls += 1;
+                    stats.triangles += 2;
+                    bindings.set_pipeline(rp, gpu, Pipeline::Quad2d, stats);
+                    bindings.set_texture(rp, textures, *sprite_index, stats);
+                    bindings.set_buffers(rp, gpu, meshes, BufferBinding::Quad, true);
                     rp.draw_indexed(0..6, 0, current_model_matrix - 1..current_model_matrix)
                 }
+                Command::GpuMarker(marker) => {
+                    if let Some(gpu_timer) = &gpu.gpu_timer {
+                        gpu_timer.write_marker(rp, *marker);
+                    }
+                }
+                Command::Highlight => {
+                    if let Some(draw) = last_draw {
+                        highlight(rp, gpu, meshes, draw);
+                        bindings.vertex = None;
+                    }
+                    // Later draws continue with the pipeline that was bound
+                    if let Some(pipeline) = bindings.pipeline {
+                        rp.set_pipeline(&gpu.render_pipelines[pipeline.get_shader()]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn draw_batch(
+    rp: &mut wgpu::RenderPass,
+    gpu: &Renderer,
+    meshes: &PreloadedRenderer,
+    bindings: &mut Bindings,
+    index: usize,
+    batch: IndirectBatch,
+    stats: &mut FrameStats,
+) {
+    let Some(indirect_draws) = &gpu.indirect_draws else {
+        return;
+    };
+    bindings.set_pipeline(rp, gpu, batch.pipeline, stats);
+    bindings.set_buffers(rp, gpu, meshes, BufferBinding::Arena(batch.arena), true);
+    stats.indirect_calls += match &gpu.gpu_culling {
+        Some(gpu_culling) => gpu_culling.draw(rp, indirect_draws, index, batch),
+        None => indirect_draws.draw(rp, &indirect_draws.buffer, batch.first, batch.count),
+    };
+}
+
+// Bindings other than the pipeline are still those of the draw
+fn highlight(
+    rp: &mut wgpu::RenderPass,
+    gpu: &Renderer,
+    meshes: &PreloadedRenderer,
+    draw: LastDraw,
+) {
+    let instances = draw.instance..draw.instance + 1;
+    rp.set_pipeline(&gpu.highlight_pipelines[draw.pipeline.get_shader()]);
+    match draw.command {
+        Command::Draw(vertex_count) => {
+            rp.set_vertex_buffer(
+                VERTEX_BUFFER_INDEX,
+                gpu.immediate_renderer.buffer.slice(draw.byte_index..),
+            );
+            rp.draw(0..vertex_count, instances);
+        }
+        Command::DrawStaticMesh(index) => {
+            if let Some(mesh) = &meshes.meshes[index] {
+                rp.draw(mesh.vertices(), instances);
+            }
+        }
+        Command::DrawStaticMeshIndexed(index) => {
+            if let Some(mesh) = &meshes.indexed_meshes[index] {
+                rp.draw_indexed(mesh.index_range(), mesh.base_vertex as i32, instances);
             }
         }
+        Command::DrawSprite(_) => rp.draw_indexed(0..6, 0, instances),
+        _ => {}
     }
 }
@@ -1,12 +1,15 @@
+use std::fmt;
+
 use pollster::FutureExt;
-use wgpu::{
-    Adapter, Device, Instance, MemoryHints, PresentMode, Queue, Surface, SurfaceCapabilities,
-};
+use wgpu::{Adapter, Device, Instance, MemoryHints, Queue, Surface, SurfaceCapabilities};
 use winit::dpi::PhysicalSize;
 
+use crate::display::DisplaySettings;
+
 pub fn create_surface_config(
     size: PhysicalSize<u32>,
     capabilities: SurfaceCapabilities,
+    display: &DisplaySettings,
 ) -> wgpu::SurfaceConfiguration {
     let surface_format = capabilities
         .formats
@@ -20,18 +23,19 @@ pub fn create_surface_config(
         format: surface_format,
         width: size.width,
         height: size.height,
-        present_mode: PresentMode::AutoVsync,
+        present_mode: display.present_mode,
         alpha_mode: capabilities.alpha_modes[0],
         view_formats: vec![],
-        desired_maximum_frame_latency: 2,
+        desired_maximum_frame_latency: display.frame_latency,
     }
 }
 
-pub fn create_device(adapter: &Adapter) -> (Device, Queue) {
+/// Creates the device, with whichever of the optional features the adapter supports.
+pub fn create_device(adapter: &Adapter, optional_features: wgpu::Features) -> (Device, Queue) {
     adapter
         .request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
+                required_features: adapter.features() & optional_features,
                 required_limits: wgpu::Limits::default(),
                 label: None,
                 memory_hints: MemoryHints::default(),
@@ -42,20 +46,102 @@ pub fn create_device(adapter: &Adapter) -> (Device, Queue) {
         .unwrap()
 }
 
-pub fn create_adapter(instance: Instance, surface: &Surface) -> Adapter {
-    instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: Some(surface),
-            force_fallback_adapter: false,
+/// Requests a hardware adapter, unless `force_fallback_adapter` is set. If no
+/// hardware adapter is available, falls back to a software adapter instead.
+pub fn create_adapter(
+    instance: &Instance,
+    surface: Option<&Surface>,
+    backends: wgpu::Backends,
+    force_fallback_adapter: bool,
+) -> Result<Adapter, AdapterNotFound> {
+    let request = |force_fallback_adapter| {
+        instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: surface,
+                force_fallback_adapter,
+            })
+            .block_on()
+    };
+
+    let adapter = if force_fallback_adapter {
+        request(true)
+    } else {
+        request(false).or_else(|| {
+            println!("No hardware adapter found, trying a fallback adapter.");
+            for adapter in instance.enumerate_adapters(backends) {
+                println!(
+                    "Enumerated adapter: {}",
+                    describe_adapter(&adapter.get_info())
+                );
+            }
+            request(true)
         })
-        .block_on()
-        .unwrap()
+    };
+
+    match adapter {
+        Some(adapter) => {
+            let info = adapter.get_info();
+            println!(
+                "Using adapter: {} ({:?}, {:?})",
+                info.name, info.device_type, info.backend
+            );
+            if info.device_type == wgpu::DeviceType::Cpu {
+                println!("Using software rendering, expect it to be slow.");
+            }
+            Ok(adapter)
+        }
+        None => Err(AdapterNotFound {
+            backends,
+            force_fallback_adapter,
+            adapters: instance
+                .enumerate_adapters(backends)
+                .iter()
+                .map(Adapter::get_info)
+                .collect(),
+        }),
+    }
+}
+
+#[derive(Debug)]
+pub struct AdapterNotFound {
+    pub backends: wgpu::Backends,
+    pub force_fallback_adapter: bool,
+    pub adapters: Vec<wgpu::AdapterInfo>,
+}
+
+impl fmt::Display for AdapterNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No suitable adapter found for backends {:?} (force fallback: {}).",
+            self.backends, self.force_fallback_adapter
+        )?;
+
+        if self.adapters.is_empty() {
+            return write!(f, " No adapters were enumerated.");
+        }
+
+        write!(f, " Enumerated adapters:")?;
+        for info in self.adapters.iter() {
+            write!(f, "\n - {}", describe_adapter(info))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AdapterNotFound {}
+
+fn describe_adapter(info: &wgpu::AdapterInfo) -> String {
+    format!(
+        "{} ({:?}, {:?}, driver: {} {})",
+        info.name, info.device_type, info.backend, info.driver, info.driver_info
+    )
 }
 
-pub fn create_gpu_instance() -> Instance {
+pub fn create_gpu_instance(backends: wgpu::Backends) -> Instance {
     Instance::new(wgpu::InstanceDescriptor {
-        backends: wgpu::Backends::PRIMARY,
+        backends,
         ..Default::default()
     })
 }